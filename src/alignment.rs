@@ -0,0 +1,210 @@
+use image::GrayImage;
+
+use crate::{ocr::OcrLine, positioning_structs::{HocrWord, PixelPoint}};
+
+/// Fraction of the line height searched above, below and beside the engine's boxes for ink belonging to the line.
+const SEARCH_MARGIN: f32 = 0.25;
+/// Runs of ink wider than this many line heights are assumed to be several touching characters.
+const MAX_GLYPH_WIDTH: f32 = 1.5;
+
+/// Snap each word's bounding box to the ink it covers in the binarized image.
+///
+/// Tesseract's character boxes are often offset from the glyphs they belong to, so the ink within each line is
+/// split into runs using a projection onto the x axis, each run is assigned to the word whose box is closest, and
+/// the word's box is shrunk to fit its ink vertically. Words without any ink nearby keep their original box.
+/// Boxes are in the coordinates of the given image. Vertical lines are left as they are, since the projection
+/// assumes characters sit side by side.
+pub fn align_to_ink(lines: Vec<OcrLine>, image: &GrayImage) -> Vec<OcrLine> {
+    return lines.into_iter()
+        .map(|line| match line.is_vertical() {
+            true => line,
+            false => OcrLine::new(align_line(line.into_words(), image)),
+        })
+        .collect();
+}
+
+fn align_line(words: Vec<HocrWord>, image: &GrayImage) -> Vec<HocrWord> {
+    if words.is_empty() || image.width() == 0 || image.height() == 0 {
+        return words;
+    }
+    let line_min_y = words.iter().map(|word| word.get_min().get_y()).fold(f32::MAX, f32::min);
+    let line_max_y = words.iter().map(|word| word.get_max().get_y()).fold(f32::MIN, f32::max);
+    let line_min_x = words.iter().map(|word| word.get_min().get_x()).fold(f32::MAX, f32::min);
+    let line_max_x = words.iter().map(|word| word.get_max().get_x()).fold(f32::MIN, f32::max);
+    let line_height = line_max_y - line_min_y;
+    if line_height < 1.0 {
+        return words;
+    }
+    let margin = line_height * SEARCH_MARGIN;
+    let left = (line_min_x - margin).max(0.0) as u32;
+    let right = ((line_max_x + margin).max(0.0) as u32).min(image.width());
+    let top = (line_min_y - margin).max(0.0) as u32;
+    let bottom = ((line_max_y + margin).max(0.0) as u32).min(image.height());
+    if right <= left || bottom <= top {
+        return words;
+    }
+
+    //Ink is whichever colour is in the minority around the line, so this works regardless of polarity
+    let dark_pixels = (top..bottom)
+        .flat_map(|y| (left..right).map(move |x| (x, y)))
+        .filter(|(x, y)| image.get_pixel(*x, *y)[0] < 128)
+        .count();
+    let is_ink_dark = dark_pixels * 2 <= ((right - left) * (bottom - top)) as usize;
+    let is_ink = |x: u32, y: u32| (image.get_pixel(x, y)[0] < 128) == is_ink_dark;
+
+    let column_ink: Vec<bool> = (left..right)
+        .map(|x| (top..bottom).any(|y| is_ink(x, y)))
+        .collect();
+    let centres: Vec<f32> = words.iter()
+        .map(|word| (word.get_min().get_x() + word.get_max().get_x()) / 2.0)
+        .collect();
+    let mut spans: Vec<Option<(u32, u32)>> = vec![None; words.len()];
+    for run in ink_runs(&column_ink, left) {
+        for (start, end) in split_run(run, &centres, line_height) {
+            let run_centre = (start + end) as f32 / 2.0;
+            let nearest = words.iter().enumerate()
+                .map(|(index, word)| {
+                    let edge_distance = (word.get_min().get_x() - run_centre).max(run_centre - word.get_max().get_x()).max(0.0);
+                    (index, edge_distance, (centres[index] - run_centre).abs())
+                })
+                .min_by(|lhs, rhs| lhs.1.total_cmp(&rhs.1).then(lhs.2.total_cmp(&rhs.2)));
+            if let Some((index, edge_distance, _)) = nearest {
+                if edge_distance > line_height { //Stray ink which doesn't belong to any recognized word
+                    continue;
+                }
+                spans[index] = Some(match spans[index] {
+                    Some((span_start, span_end)) => (span_start.min(start), span_end.max(end)),
+                    None => (start, end),
+                });
+            }
+        }
+    }
+
+    return words.into_iter().zip(spans)
+        .map(|(word, span)| {
+            let (start, end) = match span {
+                Some(span) => span,
+                None => return word,
+            };
+            let mut ink_rows = (top..bottom).filter(|y| (start..end).any(|x| is_ink(x, *y)));
+            let first_row = match ink_rows.next() {
+                Some(row) => row,
+                None => return word,
+            };
+            let last_row = ink_rows.next_back().unwrap_or(first_row);
+            word.with_bounds(PixelPoint::new(start as f32, first_row as f32), PixelPoint::new(end as f32, (last_row + 1) as f32))
+        })
+        .collect();
+}
+
+/// Contiguous ranges of columns containing ink, as `(start, end)` with `end` exclusive.
+fn ink_runs(column_ink: &[bool], left: u32) -> Vec<(u32, u32)> {
+    let mut runs = Vec::new();
+    let mut run_start = None;
+    for (offset, has_ink) in column_ink.iter().enumerate() {
+        let x = left + offset as u32;
+        match (run_start, has_ink) {
+            (None, true) => run_start = Some(x),
+            (Some(start), false) => {
+                runs.push((start, x));
+                run_start = None;
+            },
+            _ => {},
+        }
+    }
+    if let Some(start) = run_start {
+        runs.push((start, left + column_ink.len() as u32));
+    }
+    return runs;
+}
+
+/// Split a run which is too wide to be a single glyph halfway between the centres of the words it covers.
+fn split_run(run: (u32, u32), centres: &[f32], line_height: f32) -> Vec<(u32, u32)> {
+    let (start, end) = run;
+    if ((end - start) as f32) <= line_height * MAX_GLYPH_WIDTH {
+        return vec![run];
+    }
+    let mut pieces = Vec::new();
+    let mut piece_start = start;
+    for pair in centres.windows(2) {
+        let boundary = ((pair[0] + pair[1]) / 2.0).round() as u32;
+        if boundary > piece_start && boundary < end {
+            pieces.push((piece_start, boundary));
+            piece_start = boundary;
+        }
+    }
+    pieces.push((piece_start, end));
+    return pieces;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    const INK: u8 = 0;
+    const PAPER: u8 = 255;
+
+    /// An image of the given size with the given `(x0, y0, x1, y1)` rectangles filled in as ink.
+    fn image_with_glyphs(width: u32, height: u32, glyphs: &[(u32, u32, u32, u32)], ink: u8, paper: u8) -> GrayImage {
+        return GrayImage::from_fn(width, height, |x, y| {
+            match glyphs.iter().any(|(x0, y0, x1, y1)| x >= *x0 && x < *x1 && y >= *y0 && y < *y1) {
+                true => Luma([ink]),
+                false => Luma([paper]),
+            }
+        });
+    }
+
+    fn word(text: &str, x0: f32, y0: f32, x1: f32, y1: f32) -> HocrWord {
+        HocrWord::new(text.to_string(), PixelPoint::new(x0, y0), PixelPoint::new(x1, y1), 95.0)
+    }
+
+    fn aligned_bounds(words: Vec<HocrWord>, image: &GrayImage) -> Vec<(f32, f32, f32, f32)> {
+        let lines = align_to_ink(vec![OcrLine::new(words)], image);
+        return lines[0].get_words().iter()
+            .map(|word| (word.get_min().get_x(), word.get_min().get_y(), word.get_max().get_x(), word.get_max().get_y()))
+            .collect();
+    }
+
+    #[test]
+    fn snaps_offset_box_to_ink() {
+        let image = image_with_glyphs(100, 40, &[(20, 10, 40, 30)], INK, PAPER);
+        let bounds = aligned_bounds(vec![word("你", 17.0, 7.0, 37.0, 27.0)], &image);
+        assert_eq!(bounds, vec![(20.0, 10.0, 40.0, 30.0)]);
+    }
+
+    #[test]
+    fn works_with_light_text_on_dark_background() {
+        let image = image_with_glyphs(100, 40, &[(20, 10, 40, 30)], PAPER, INK);
+        let bounds = aligned_bounds(vec![word("你", 17.0, 7.0, 37.0, 27.0)], &image);
+        assert_eq!(bounds, vec![(20.0, 10.0, 40.0, 30.0)]);
+    }
+
+    #[test]
+    fn assigns_each_glyph_to_the_nearest_word() {
+        let image = image_with_glyphs(100, 40, &[(10, 10, 30, 30), (40, 10, 60, 30)], INK, PAPER);
+        let bounds = aligned_bounds(vec![word("你", 12.0, 8.0, 32.0, 28.0), word("好", 38.0, 8.0, 58.0, 28.0)], &image);
+        assert_eq!(bounds, vec![(10.0, 10.0, 30.0, 30.0), (40.0, 10.0, 60.0, 30.0)]);
+    }
+
+    #[test]
+    fn splits_touching_glyphs_between_words() {
+        let image = image_with_glyphs(100, 40, &[(10, 12, 50, 28)], INK, PAPER);
+        let bounds = aligned_bounds(vec![word("你", 10.0, 10.0, 30.0, 30.0), word("好", 30.0, 10.0, 50.0, 30.0)], &image);
+        assert_eq!(bounds, vec![(10.0, 12.0, 30.0, 28.0), (30.0, 12.0, 50.0, 28.0)]);
+    }
+
+    #[test]
+    fn keeps_box_of_word_without_ink() {
+        let image = image_with_glyphs(100, 40, &[(20, 10, 40, 30)], INK, PAPER);
+        let bounds = aligned_bounds(vec![word("你", 17.0, 7.0, 37.0, 27.0), word("好", 60.0, 7.0, 80.0, 27.0)], &image);
+        assert_eq!(bounds, vec![(20.0, 10.0, 40.0, 30.0), (60.0, 7.0, 80.0, 27.0)]);
+    }
+
+    #[test]
+    fn ignores_stray_ink_between_distant_words() {
+        let image = image_with_glyphs(140, 40, &[(10, 10, 30, 30), (60, 15, 64, 25), (100, 10, 120, 30)], INK, PAPER);
+        let bounds = aligned_bounds(vec![word("你", 10.0, 10.0, 30.0, 30.0), word("好", 100.0, 10.0, 120.0, 30.0)], &image);
+        assert_eq!(bounds, vec![(10.0, 10.0, 30.0, 30.0), (100.0, 10.0, 120.0, 30.0)]);
+    }
+}
@@ -0,0 +1,195 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+use serde::Serialize;
+
+use crate::{dictionary, engine::{OcrEngine, TesseractEngine}, ocr::{self, OcrLine, OcrError, OcrResult}, positioning_structs::{HocrWord, OcrSymbol}, preprocessing::{Polarity, PreprocessingPipeline}, supported_languages::SupportedLanguages};
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "bmp", "webp"];
+
+#[derive(Debug, Serialize)]
+pub struct ImageReport {
+    path: PathBuf,
+    detected_polarity: Polarity,
+    lines: Vec<LineReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct LineReport {
+    text: String,
+    /// Tesseract's reading, when dictionary correction changed it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_text: Option<String>,
+    tokens: Vec<TokenReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenReport {
+    text: String,
+    bbox: [f32; 4],
+    confidence: f32,
+    symbols: Vec<SymbolReport>,
+    translations: Vec<TranslationReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct SymbolReport {
+    text: String,
+    bbox: [f32; 4],
+    confidence: f32,
+    choices: Vec<ChoiceReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChoiceReport {
+    text: String,
+    confidence: f32,
+}
+
+#[derive(Debug, Serialize)]
+struct TranslationReport {
+    traditional: String,
+    simplified: String,
+    /// The reading in pinyin for Chinese, or as given by the dictionary file in other languages
+    pinyin: String,
+    english: Vec<String>,
+}
+
+impl ImageReport {
+    fn new(path: PathBuf, result: OcrResult, language: SupportedLanguages) -> Self {
+        Self {
+            path,
+            detected_polarity: result.get_detected_polarity(),
+            lines: result.get_lines().iter().map(|line| LineReport::new(line, language)).collect(),
+        }
+    }
+}
+
+impl LineReport {
+    fn new(line: &OcrLine, language: SupportedLanguages) -> Self {
+        Self {
+            text: line.get_text(),
+            raw_text: Some(line.get_raw_text()).filter(|raw_text| *raw_text != line.get_text()),
+            tokens: line.get_words().iter().map(|word| TokenReport::new(word, language)).collect(),
+        }
+    }
+}
+
+impl TokenReport {
+    fn new(word: &HocrWord, language: SupportedLanguages) -> Self {
+        let min = word.get_min();
+        let max = word.get_max();
+        let translations = dictionary::query(language.get_script(), word.get_text()).iter()
+            .map(|translation| TranslationReport {
+                traditional: translation.get_traditional().clone(),
+                simplified: translation.get_simplified().clone(),
+                pinyin: translation.get_reading().clone(),
+                english: translation.get_english().clone(),
+            })
+            .collect();
+        Self {
+            text: word.get_text().clone(),
+            bbox: [min.get_x(), min.get_y(), max.get_x(), max.get_y()],
+            confidence: word.get_confidence(),
+            symbols: word.get_symbols().iter().map(SymbolReport::from).collect(),
+            translations,
+        }
+    }
+}
+
+impl From<&OcrSymbol> for SymbolReport {
+    fn from(symbol: &OcrSymbol) -> Self {
+        let min = symbol.get_min();
+        let max = symbol.get_max();
+        Self {
+            text: symbol.get_text().clone(),
+            bbox: [min.get_x(), min.get_y(), max.get_x(), max.get_y()],
+            confidence: symbol.get_confidence(),
+            choices: symbol.get_choices().iter()
+                .map(|choice| ChoiceReport {
+                    text: choice.get_text().clone(),
+                    confidence: choice.get_confidence(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Expand the given paths into a sorted list of image files, looking one level into any directories.
+pub fn collect_image_paths(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut image_paths = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut dir_paths = fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && is_image_path(path))
+                .collect::<Vec<_>>();
+            dir_paths.sort();
+            image_paths.append(&mut dir_paths);
+        } else {
+            image_paths.push(path.clone());
+        }
+    }
+    return Ok(image_paths);
+}
+
+fn is_image_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+pub fn process_image<E: OcrEngine>(path: &Path, engine: &mut E, pipeline: &PreprocessingPipeline, debug_dump_dir: Option<&Path>) -> Result<ImageReport, OcrError> {
+    let image = image::open(path)?;
+    let result = ocr::ocr_image_with_engine(&image, engine, pipeline, debug_dump_dir)?;
+    return Ok(ImageReport::new(path.to_path_buf(), result, engine.get_language()));
+}
+
+/// OCR every image in `paths`, printing one JSON object per image to stdout.
+/// Images which fail are reported on stderr and skipped.
+pub fn run_batch(paths: &[PathBuf], language: SupportedLanguages, pipeline: &PreprocessingPipeline, debug_dump_dir: Option<&Path>) -> io::Result<()> {
+    let mut engine = TesseractEngine::new(language);
+    for path in collect_image_paths(paths)? {
+        match process_image(&path, &mut engine, pipeline, debug_dump_dir) {
+            Ok(report) => match serde_json::to_string(&report) {
+                Ok(json) => println!("{}", json),
+                Err(e) => eprintln!("{}: {}", path.display(), e),
+            },
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        }
+    }
+    if let Some(metrics) = engine.get_metrics() {
+        log::info!("OCR engine metrics: {}", metrics);
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_image_extensions_in_any_case() {
+        assert!(is_image_path(Path::new("frame.png")));
+        assert!(is_image_path(Path::new("frame.JPG")));
+        assert!(is_image_path(Path::new("frame.JpEg")));
+        assert!(is_image_path(Path::new("captures/frame.webp")));
+        assert!(!is_image_path(Path::new("frame.txt")));
+        assert!(!is_image_path(Path::new("png")));
+        assert!(!is_image_path(Path::new("frame.png.json")));
+    }
+
+    #[test]
+    fn collects_sorted_images_from_directories() {
+        let directory = std::env::temp_dir().join(format!("decky_translate_batch_{}", std::process::id()));
+        fs::create_dir_all(directory.join("nested")).unwrap();
+        for name in ["b.PNG", "a.jpg", "c.Webp", "notes.txt", "frame.png.json", "README", "nested/d.png"] {
+            fs::write(directory.join(name), "").unwrap();
+        }
+        let explicit = directory.join("notes.txt");
+        let paths = collect_image_paths(&[explicit.clone(), directory.clone()]);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(paths.unwrap(), vec![explicit, directory.join("a.jpg"), directory.join("b.PNG"), directory.join("c.Webp")]);
+    }
+}
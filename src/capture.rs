@@ -0,0 +1,558 @@
+use std::{fs::File, io::{self, Read}, path::PathBuf, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, thread};
+#[cfg(target_os = "linux")]
+use std::{ffi::OsString, os::unix::ffi::OsStringExt, time::{Duration, Instant}};
+
+#[cfg(target_os = "linux")]
+use dbus::{Message, arg::{PropMap, Variant, prop_cast}, blocking::Connection, message::MatchRule};
+use image::{DynamicImage, RgbaImage, imageops::{self, FilterType}};
+use screenshots::Screen;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use crate::{batch, config::CaptureConfig};
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+    #[error("Failed to capture the screen: {0}")]
+    Screen(String),
+    #[error("Failed to load captured image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("Failed to read captures: {0}")]
+    Io(#[from] io::Error),
+    #[error("No images found in {0}")]
+    NoFrames(PathBuf),
+    #[error("No frames have been received from the stream yet")]
+    NoStreamFrame,
+    #[error("The stream of frames has closed")]
+    StreamClosed,
+    #[error("The {0} capture setting is missing or invalid")]
+    InvalidSetting(&'static str),
+    #[error("Failed to take a screenshot through the desktop portal: {0}")]
+    Portal(String),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum SupportedCaptureSources {
+    Screen,
+    File,
+    Stream,
+    Replay,
+    Portal,
+}
+
+/// The region of the screen underneath the overlay window, in physical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CaptureArea {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+impl CaptureArea {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn get_x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn get_y(&self) -> i32 {
+        self.y
+    }
+
+    pub fn get_width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn get_height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn intersection(&self, other: &CaptureArea) -> Option<CaptureArea> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width as i32).min(other.x + other.width as i32);
+        let bottom = (self.y + self.height as i32).min(other.y + other.height as i32);
+        if right <= x || bottom <= y {
+            return None;
+        }
+        return Some(CaptureArea::new(x, y, (right - x) as u32, (bottom - y) as u32));
+    }
+
+    /// Scale the area, such as to convert a display's logical coordinates into physical pixels.
+    pub fn scale(&self, factor: f32) -> CaptureArea {
+        let scale = |value: f32| (value * factor).round();
+        return CaptureArea::new(
+            scale(self.x as f32) as i32,
+            scale(self.y as f32) as i32,
+            scale(self.width as f32) as u32,
+            scale(self.height as f32) as u32,
+        );
+    }
+}
+
+/// Somewhere the OCR worker can grab images from.
+pub trait CaptureSource {
+    fn capture(&mut self, area: CaptureArea) -> Result<DynamicImage, CaptureError>;
+
+    /// Whether each capture shows what is happening right now, such as on the screen, so that capturing again later
+    /// can show text which has finished appearing. Sources which step through saved frames would skip frames instead.
+    fn is_live(&self) -> bool {
+        false
+    }
+}
+
+impl<C: CaptureSource + ?Sized> CaptureSource for Box<C> {
+    fn capture(&mut self, area: CaptureArea) -> Result<DynamicImage, CaptureError> {
+        (**self).capture(area)
+    }
+
+    fn is_live(&self) -> bool {
+        (**self).is_live()
+    }
+}
+
+/// Captures the live screen underneath the overlay.
+///
+/// The area may span several displays, in which case each display's part is captured separately and stitched together.
+/// Displays report their bounds in logical units, so each display is scaled by its own scale factor to find the part
+/// of the area it covers in physical pixels, and that part is captured at full resolution.
+#[derive(Debug, Default)]
+pub struct ScreenSource;
+
+impl CaptureSource for ScreenSource {
+    fn capture(&mut self, area: CaptureArea) -> Result<DynamicImage, CaptureError> {
+        if area.width == 0 || area.height == 0 {
+            return Err(CaptureError::Screen("the capture area is empty".to_string()));
+        }
+        let screens = Screen::all().map_err(|e| CaptureError::Screen(e.to_string()))?;
+        let mut canvas = RgbaImage::new(area.width, area.height);
+        let mut is_captured = false;
+        for screen in screens {
+            let display = screen.display_info;
+            let scale_factor = if display.scale_factor > 0.0 { display.scale_factor } else { 1.0 };
+            let display_bounds = CaptureArea::new(display.x, display.y, display.width, display.height);
+            let (part, logical_part) = match display_part(&area, &display_bounds, scale_factor) {
+                Some(parts) => parts,
+                None => continue,
+            };
+            let image = screen.capture_area(logical_part.x, logical_part.y, logical_part.width, logical_part.height)
+                .map_err(|e| CaptureError::Screen(e.to_string()))?;
+            let mut image = image::load_from_memory(image.buffer())?;
+            if image.width() != part.width || image.height() != part.height { //Rounding to whole logical units, at most a pixel or two out
+                image = image.resize_exact(part.width, part.height, FilterType::CatmullRom);
+            }
+            imageops::replace(&mut canvas, &image.to_rgba8(), (part.x - area.x) as i64, (part.y - area.y) as i64);
+            is_captured = true;
+        }
+        if !is_captured {
+            return Err(CaptureError::Screen("the capture area is not on any display".to_string()));
+        }
+        return Ok(DynamicImage::ImageRgba8(canvas));
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+}
+
+/// The part of the area which a display covers in physical pixels, along with the same part in logical units relative
+/// to the display, which is what the display is captured with.
+fn display_part(area: &CaptureArea, display_bounds: &CaptureArea, scale_factor: f32) -> Option<(CaptureArea, CaptureArea)> {
+    let display_area = display_bounds.scale(scale_factor);
+    let part = area.intersection(&display_area)?;
+    let logical_part = CaptureArea::new(part.x - display_area.x, part.y - display_area.y, part.width, part.height)
+        .scale(1.0 / scale_factor);
+    if logical_part.width == 0 || logical_part.height == 0 {
+        return None;
+    }
+    return Some((part, logical_part));
+}
+
+/// Replays saved captures, such as those written by the debug dump, instead of reading the screen.
+///
+/// Each capture returns the next frame whole, regardless of the requested area, looping back to the first frame at the end.
+#[derive(Debug)]
+pub struct ReplaySource {
+    frames: Vec<PathBuf>,
+    next_frame: usize,
+}
+
+impl ReplaySource {
+    /// Replay a single image, or every image in a directory in name order.
+    pub fn new(path: PathBuf) -> Result<Self, CaptureError> {
+        let frames = batch::collect_image_paths(std::slice::from_ref(&path))?;
+        if frames.is_empty() {
+            return Err(CaptureError::NoFrames(path));
+        }
+        return Ok(Self {
+            frames,
+            next_frame: 0,
+        });
+    }
+
+    pub fn get_frames(&self) -> &Vec<PathBuf> {
+        &self.frames
+    }
+}
+
+impl CaptureSource for ReplaySource {
+    fn capture(&mut self, _area: CaptureArea) -> Result<DynamicImage, CaptureError> {
+        let frame = &self.frames[self.next_frame];
+        self.next_frame = (self.next_frame + 1) % self.frames.len();
+        return Ok(image::open(frame)?);
+    }
+}
+
+/// Re-reads a single image file on every capture, for tools which repeatedly overwrite a screenshot on disk.
+///
+/// The whole image is returned, regardless of the requested area.
+#[derive(Debug)]
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+        }
+    }
+}
+
+impl CaptureSource for FileSource {
+    fn capture(&mut self, _area: CaptureArea) -> Result<DynamicImage, CaptureError> {
+        Ok(image::open(&self.path)?)
+    }
+}
+
+/// Readers which are, or were, reading a stream, shared so stdin or a FIFO is never read by two threads at once.
+static STREAM_READERS: Mutex<Vec<Arc<StreamReader>>> = Mutex::new(Vec::new());
+
+/// The state shared between a stream's reader thread and every [`StreamSource`] reading from it.
+struct StreamReader {
+    path: String,
+    frame_size: Mutex<(u32, u32)>,
+    latest_frame: Mutex<Option<RgbaImage>>,
+    is_closed: AtomicBool,
+}
+
+impl StreamReader {
+    fn spawn(path: &str, width: u32, height: u32) -> Arc<Self> {
+        let stream_reader = Arc::new(Self {
+            path: path.to_string(),
+            frame_size: Mutex::new((width, height)),
+            latest_frame: Mutex::new(None),
+            is_closed: AtomicBool::new(false),
+        });
+        let thread_stream_reader = stream_reader.clone();
+        thread::spawn(move || {
+            //Opening a FIFO blocks until something opens the other end, so it has to happen on this thread
+            let reader: Box<dyn Read> = match thread_stream_reader.path.as_str() {
+                "-" => Box::new(io::stdin()),
+                path => match File::open(path) {
+                    Ok(file) => Box::new(file),
+                    Err(e) => {
+                        log::error!("Failed to open capture stream {}: {}", path, e);
+                        thread_stream_reader.close();
+                        return;
+                    },
+                },
+            };
+            thread_stream_reader.read_frames(reader);
+        });
+        return stream_reader;
+    }
+
+    fn read_frames(&self, mut reader: Box<dyn Read>) {
+        loop {
+            let (width, height) = *self.frame_size.lock().unwrap();
+            let mut buffer = vec![0u8; width as usize * height as usize * 4];
+            if reader.read_exact(&mut buffer).is_err() {
+                break;
+            }
+            *self.latest_frame.lock().unwrap() = RgbaImage::from_raw(width, height, buffer);
+        }
+        log::info!("Capture stream {} closed", self.path);
+        self.close();
+    }
+
+    fn close(&self) {
+        self.is_closed.store(true, Ordering::SeqCst);
+        *self.latest_frame.lock().unwrap() = None;
+    }
+}
+
+/// Reads raw RGBA frames of a fixed size from stdin or a FIFO, such as the output of
+/// `ffmpeg -f rawvideo -pix_fmt rgba`, so frames can be piped in from an emulator.
+///
+/// Frames are read continuously on a background thread so the pipe never backs up,
+/// and each capture returns the most recent whole frame, regardless of the requested area.
+/// The thread runs until the stream closes, and opening the same path again, such as after switching profiles,
+/// reuses it rather than starting a second thread which would steal half of every frame.
+pub struct StreamSource {
+    stream_reader: Arc<StreamReader>,
+}
+
+impl StreamSource {
+    /// Read from the given path, or from stdin when the path is `-`.
+    ///
+    /// The path is opened on the reader thread, so a FIFO with nothing writing to it yet doesn't block the caller.
+    /// If the path is already being read, the existing reader switches to the new frame size from its next frame.
+    pub fn open(path: &str, width: u32, height: u32) -> Self {
+        let mut stream_readers = STREAM_READERS.lock().unwrap();
+        //A FIFO can be reopened once its writer restarts, but stdin stays closed for good
+        stream_readers.retain(|stream_reader| !stream_reader.is_closed.load(Ordering::SeqCst) || stream_reader.path == "-");
+        let stream_reader = match stream_readers.iter().find(|stream_reader| stream_reader.path == path) {
+            Some(stream_reader) => {
+                *stream_reader.frame_size.lock().unwrap() = (width, height);
+                stream_reader.clone()
+            },
+            None => {
+                let stream_reader = StreamReader::spawn(path, width, height);
+                stream_readers.push(stream_reader.clone());
+                stream_reader
+            },
+        };
+        return Self {
+            stream_reader,
+        };
+    }
+}
+
+impl CaptureSource for StreamSource {
+    fn capture(&mut self, _area: CaptureArea) -> Result<DynamicImage, CaptureError> {
+        if self.stream_reader.is_closed.load(Ordering::SeqCst) {
+            return Err(CaptureError::StreamClosed);
+        }
+        match &*self.stream_reader.latest_frame.lock().unwrap() {
+            Some(frame) => Ok(DynamicImage::ImageRgba8(frame.clone())),
+            None => Err(CaptureError::NoStreamFrame),
+        }
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+}
+
+/// Takes screenshots through the XDG desktop portal, which is the only way to read the screen under Wayland.
+///
+/// Each capture asks the portal for a non-interactive screenshot of the whole desktop over the session bus, waits for
+/// the request's `Response` signal, crops the saved image to the area and deletes it. The compositor may still ask the
+/// user to allow the first screenshot, and the area is only right if the overlay knows where its window is, which some
+/// Wayland compositors don't tell it.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Default)]
+pub struct PortalSource {
+    next_request: u32,
+}
+
+#[cfg(target_os = "linux")]
+impl PortalSource {
+    const DESTINATION: &'static str = "org.freedesktop.portal.Desktop";
+    const PATH: &'static str = "/org/freedesktop/portal/desktop";
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Ask the portal for a screenshot and return the path it was saved to.
+    fn take_screenshot(&mut self) -> Result<PathBuf, CaptureError> {
+        let portal_error = |e: dbus::Error| CaptureError::Portal(e.to_string());
+        let connection = Connection::new_session().map_err(portal_error)?;
+        let token = format!("decky_translate_{}_{}", std::process::id(), self.next_request);
+        self.next_request = self.next_request.wrapping_add(1);
+
+        //Listen before calling, since the response can arrive before the call returns
+        let response: Arc<Mutex<Option<Result<String, CaptureError>>>> = Arc::new(Mutex::new(None));
+        let callback_response = response.clone();
+        let rule = MatchRule::new_signal("org.freedesktop.portal.Request", "Response")
+            .with_path(request_path(&connection.unique_name(), &token));
+        connection.add_match(rule, move |(code, results): (u32, PropMap), _: &Connection, _: &Message| {
+            let uri = match code {
+                0 => prop_cast::<String>(&results, "uri").cloned()
+                    .ok_or_else(|| CaptureError::Portal("the response has no uri".to_string())),
+                1 => Err(CaptureError::Portal("the screenshot was cancelled".to_string())),
+                _ => Err(CaptureError::Portal(format!("the request failed with response {}", code))),
+            };
+            *callback_response.lock().unwrap() = Some(uri);
+            false
+        }).map_err(portal_error)?;
+
+        let mut options = PropMap::new();
+        options.insert("handle_token".to_string(), Variant(Box::new(token)));
+        options.insert("interactive".to_string(), Variant(Box::new(false)));
+        connection.with_proxy(Self::DESTINATION, Self::PATH, Self::TIMEOUT)
+            .method_call::<(dbus::Path<'static>,), _, _, _>("org.freedesktop.portal.Screenshot", "Screenshot", ("", options))
+            .map_err(portal_error)?;
+
+        let deadline = Instant::now() + Self::TIMEOUT;
+        loop {
+            if let Some(uri) = response.lock().unwrap().take() {
+                let uri = uri?;
+                return file_uri_to_path(&uri).ok_or(CaptureError::Portal(format!("{} is not a local file", uri)));
+            }
+            if Instant::now() >= deadline {
+                return Err(CaptureError::Portal("timed out waiting for the screenshot".to_string()));
+            }
+            connection.process(Duration::from_millis(100)).map_err(portal_error)?;
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl CaptureSource for PortalSource {
+    fn capture(&mut self, area: CaptureArea) -> Result<DynamicImage, CaptureError> {
+        let path = self.take_screenshot()?;
+        let screenshot = image::open(&path);
+        if let Err(e) = std::fs::remove_file(&path) {
+            log::warn!("Failed to delete the portal screenshot {}: {}", path.display(), e);
+        }
+        return crop_to_area(&screenshot?, area);
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+}
+
+/// The object path of the `Request` the portal creates for a call made with `handle_token`, which is where its
+/// `Response` signal is sent from. The sender's unique name, such as `:1.42`, loses its colon and has its dots replaced.
+#[cfg(target_os = "linux")]
+fn request_path(sender: &str, token: &str) -> String {
+    return format!("/org/freedesktop/portal/desktop/request/{}/{}", sender.trim_start_matches(':').replace('.', "_"), token);
+}
+
+/// The local path of a `file://` URI, with any percent encoded bytes, such as spaces, decoded.
+#[cfg(target_os = "linux")]
+fn file_uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut path = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        match encoded[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(encoded.get(i + 1..i + 3)?).ok()?;
+                path.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            },
+            byte => {
+                path.push(byte);
+                i += 1;
+            },
+        }
+    }
+    return Some(PathBuf::from(OsString::from_vec(path)));
+}
+
+/// The part of a screenshot of the whole desktop which is underneath the area.
+#[cfg(target_os = "linux")]
+fn crop_to_area(screenshot: &DynamicImage, area: CaptureArea) -> Result<DynamicImage, CaptureError> {
+    let bounds = CaptureArea::new(0, 0, screenshot.width(), screenshot.height());
+    let part = area.intersection(&bounds)
+        .ok_or(CaptureError::Portal("the capture area is outside the screenshot".to_string()))?;
+    return Ok(screenshot.crop_imm(part.x as u32, part.y as u32, part.width, part.height));
+}
+
+/// Build the capture source selected by the `[capture]` table of the config.
+pub fn from_config(config: &CaptureConfig) -> Result<Box<dyn CaptureSource + Send>, CaptureError> {
+    let path = || config.path.clone().ok_or(CaptureError::InvalidSetting("path"));
+    let dimension = |key: &'static str, value: Option<u32>| value
+        .filter(|value| *value > 0)
+        .ok_or(CaptureError::InvalidSetting(key));
+    let capture_source: Box<dyn CaptureSource + Send> = match config.source {
+        SupportedCaptureSources::Screen => Box::new(ScreenSource),
+        SupportedCaptureSources::File => Box::new(FileSource::new(PathBuf::from(path()?))),
+        SupportedCaptureSources::Stream => Box::new(StreamSource::open(&path()?, dimension("width", config.width)?, dimension("height", config.height)?)),
+        SupportedCaptureSources::Replay => Box::new(ReplaySource::new(PathBuf::from(path()?))?),
+        #[cfg(target_os = "linux")]
+        SupportedCaptureSources::Portal => Box::new(PortalSource::default()),
+        #[cfg(not(target_os = "linux"))]
+        SupportedCaptureSources::Portal => return Err(CaptureError::Portal("the desktop portal is only available on Linux".to_string())),
+    };
+    return Ok(capture_source);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disjoint_areas_do_not_intersect() {
+        let area = CaptureArea::new(0, 0, 100, 100);
+        assert_eq!(area.intersection(&CaptureArea::new(100, 0, 50, 50)), None); //Touching edges share no pixels
+        assert_eq!(area.intersection(&CaptureArea::new(-50, 100, 50, 50)), None);
+        assert_eq!(area.intersection(&CaptureArea::new(300, 300, 10, 10)), None);
+        assert_eq!(area.intersection(&CaptureArea::new(20, 20, 0, 10)), None);
+    }
+
+    #[test]
+    fn intersects_partial_overlap() {
+        let area = CaptureArea::new(50, 20, 100, 60);
+        let display = CaptureArea::new(0, 0, 120, 1080);
+        assert_eq!(area.intersection(&display), Some(CaptureArea::new(50, 20, 70, 60)));
+        assert_eq!(display.intersection(&area), Some(CaptureArea::new(50, 20, 70, 60)));
+        assert_eq!(CaptureArea::new(-50, 10, 100, 20).intersection(&CaptureArea::new(-1920, 0, 1920, 1080)), Some(CaptureArea::new(-50, 10, 50, 20)));
+        assert_eq!(area.intersection(&CaptureArea::new(60, 30, 10, 10)), Some(CaptureArea::new(60, 30, 10, 10)));
+    }
+
+    #[test]
+    fn scales_by_non_integer_factors() {
+        assert_eq!(CaptureArea::new(1920, 0, 1280, 720).scale(1.5), CaptureArea::new(2880, 0, 1920, 1080));
+        assert_eq!(CaptureArea::new(3, 5, 7, 9).scale(1.25), CaptureArea::new(4, 6, 9, 11));
+        assert_eq!(CaptureArea::new(-3, 5, 7, 9).scale(1.25), CaptureArea::new(-4, 6, 9, 11));
+        assert_eq!(CaptureArea::new(2880, 0, 1920, 1080).scale(1.0 / 1.5), CaptureArea::new(1920, 0, 1280, 720));
+    }
+
+    #[test]
+    fn splits_area_across_displays() {
+        let left = CaptureArea::new(-1920, 0, 1920, 1080);
+        let right = CaptureArea::new(0, 0, 1280, 720); //Scaled by 1.5 to 1920x1080 physical pixels
+        let area = CaptureArea::new(-100, 150, 400, 100);
+        assert_eq!(display_part(&area, &left, 1.0), Some((CaptureArea::new(-100, 150, 100, 100), CaptureArea::new(1820, 150, 100, 100))));
+        assert_eq!(display_part(&area, &right, 1.5), Some((CaptureArea::new(0, 150, 300, 100), CaptureArea::new(0, 100, 200, 67))));
+        assert_eq!(display_part(&CaptureArea::new(300, 150, 601, 301), &right, 1.5), Some((CaptureArea::new(300, 150, 601, 301), CaptureArea::new(200, 100, 401, 201))));
+        assert_eq!(display_part(&CaptureArea::new(2000, 0, 100, 100), &right, 1.5), None);
+    }
+
+    #[test]
+    fn skips_slivers_too_thin_to_capture() {
+        //A single physical pixel at a scale of 3 rounds to no logical pixels at all
+        let display = CaptureArea::new(0, 0, 640, 360);
+        assert_eq!(display_part(&CaptureArea::new(1919, 0, 100, 100), &display, 3.0), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn finds_the_portal_request_path() {
+        assert_eq!(request_path(":1.42", "decky_translate_7_0"), "/org/freedesktop/portal/desktop/request/1_42/decky_translate_7_0");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn decodes_portal_file_uris() {
+        assert_eq!(file_uri_to_path("file:///home/deck/Pictures/Screenshot.png"), Some(PathBuf::from("/home/deck/Pictures/Screenshot.png")));
+        assert_eq!(file_uri_to_path("file:///tmp/Screenshot%20from%202024.png"), Some(PathBuf::from("/tmp/Screenshot from 2024.png")));
+        assert_eq!(file_uri_to_path("file:///tmp/%E6%88%AA%E5%9C%96.png"), Some(PathBuf::from("/tmp/截圖.png")));
+        assert_eq!(file_uri_to_path("file:///tmp/broken%2"), None);
+        assert_eq!(file_uri_to_path("https://example.com/Screenshot.png"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn crops_portal_screenshots_to_the_area() {
+        let screenshot = DynamicImage::ImageRgba8(RgbaImage::from_fn(100, 50, |x, y| image::Rgba([x as u8, y as u8, 0, 255])));
+        let crop = crop_to_area(&screenshot, CaptureArea::new(10, 20, 30, 5)).unwrap().to_rgba8();
+        assert_eq!(crop.dimensions(), (30, 5));
+        assert_eq!(crop.get_pixel(0, 0), &image::Rgba([10, 20, 0, 255]));
+
+        let clipped = crop_to_area(&screenshot, CaptureArea::new(-10, 40, 30, 30)).unwrap();
+        assert_eq!((clipped.width(), clipped.height()), (20, 10));
+        assert!(crop_to_area(&screenshot, CaptureArea::new(100, 0, 10, 10)).is_err());
+    }
+}
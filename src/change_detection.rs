@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use image::{DynamicImage, imageops::FilterType};
+
+const FINGERPRINT_SIZE: u32 = 32;
+
+/// How often watch mode polls the capture area, and how different a frame must be to trigger a new scan.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchSettings {
+    interval: Duration,
+    threshold: f32,
+}
+
+impl WatchSettings {
+    /// `threshold` is the mean difference in brightness between frames, from 0.0 to 1.0.
+    pub fn new(interval: Duration, threshold: f32) -> Self {
+        Self {
+            interval,
+            threshold,
+        }
+    }
+
+    pub fn get_interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn get_threshold(&self) -> f32 {
+        self.threshold
+    }
+}
+
+/// A tiny grayscale thumbnail of a frame, which is cheap to compare and ignores noise such as compression artifacts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameFingerprint {
+    pixels: Vec<u8>,
+}
+
+impl FrameFingerprint {
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let thumbnail = image.resize_exact(FINGERPRINT_SIZE, FINGERPRINT_SIZE, FilterType::Triangle).into_luma8();
+        return Self {
+            pixels: thumbnail.into_raw(),
+        };
+    }
+
+    /// Mean absolute difference in brightness, from 0.0 for identical frames to 1.0.
+    pub fn difference(&self, other: &FrameFingerprint) -> f32 {
+        let total: u32 = self.pixels.iter()
+            .zip(other.pixels.iter())
+            .map(|(lhs, rhs)| lhs.abs_diff(*rhs) as u32)
+            .sum();
+        return total as f32 / (self.pixels.len() as f32 * 255.0);
+    }
+}
+
+/// How long to wait for text which is still being typed out to settle before it is scanned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StabilitySettings {
+    stable_frames: u32,
+    interval: Duration,
+    threshold: f32,
+    max_wait: Duration,
+}
+
+impl StabilitySettings {
+    /// The capture is considered settled once `stable_frames` consecutive captures, taken `interval` apart,
+    /// each differ from the one before by no more than `threshold`. Scanning goes ahead anyway after `max_wait`.
+    pub fn new(stable_frames: u32, interval: Duration, threshold: f32, max_wait: Duration) -> Self {
+        Self {
+            stable_frames,
+            interval,
+            threshold,
+            max_wait,
+        }
+    }
+
+    pub fn get_stable_frames(&self) -> u32 {
+        self.stable_frames
+    }
+
+    pub fn get_interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn get_threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    pub fn get_max_wait(&self) -> Duration {
+        self.max_wait
+    }
+}
+
+/// Counts how many captures in a row have matched the one before, to tell when an animation has finished.
+#[derive(Debug)]
+pub struct StabilityDetector {
+    settings: StabilitySettings,
+    previous: Option<FrameFingerprint>,
+    stable_count: u32,
+}
+
+impl StabilityDetector {
+    pub fn new(settings: StabilitySettings) -> Self {
+        Self {
+            settings,
+            previous: None,
+            stable_count: 0,
+        }
+    }
+
+    /// Record the next capture, returning whether the last `stable_frames` captures have all matched.
+    pub fn push(&mut self, image: &DynamicImage) -> bool {
+        let fingerprint = FrameFingerprint::from_image(image);
+        match &self.previous {
+            Some(previous) if previous.difference(&fingerprint) <= self.settings.threshold => self.stable_count += 1,
+            _ => self.stable_count = 0,
+        }
+        self.previous = Some(fingerprint);
+        return self.stable_count + 1 >= self.settings.stable_frames;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn solid(brightness: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 48, Rgb([brightness; 3])))
+    }
+
+    fn detector(stable_frames: u32) -> StabilityDetector {
+        StabilityDetector::new(StabilitySettings::new(stable_frames, Duration::from_millis(100), 0.02, Duration::from_secs(2)))
+    }
+
+    #[test]
+    fn fingerprint_difference_is_normalised() {
+        let black = FrameFingerprint::from_image(&solid(0));
+        let white = FrameFingerprint::from_image(&solid(255));
+        assert_eq!(black.difference(&black), 0.0);
+        assert_eq!(black.difference(&white), 1.0);
+        assert!((black.difference(&FrameFingerprint::from_image(&solid(51))) - 0.2).abs() < 0.001);
+    }
+
+    #[test]
+    fn fingerprint_ignores_image_size() {
+        let small = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 16, Rgb([90; 3])));
+        assert_eq!(FrameFingerprint::from_image(&small).difference(&FrameFingerprint::from_image(&solid(90))), 0.0);
+    }
+
+    #[test]
+    fn settles_after_stable_frames_matching_captures() {
+        let mut detector = detector(3);
+        assert!(!detector.push(&solid(10)));
+        assert!(!detector.push(&solid(10)));
+        assert!(detector.push(&solid(10)));
+        assert!(detector.push(&solid(10)));
+    }
+
+    #[test]
+    fn change_resets_the_count() {
+        let mut detector = detector(2);
+        assert!(!detector.push(&solid(10)));
+        assert!(detector.push(&solid(10)));
+        assert!(!detector.push(&solid(200)));
+        assert!(detector.push(&solid(200)));
+    }
+
+    #[test]
+    fn differences_within_threshold_count_as_stable() {
+        let mut detector = detector(2);
+        assert!(!detector.push(&solid(100)));
+        assert!(detector.push(&solid(103)));
+    }
+
+    #[test]
+    fn single_stable_frame_settles_immediately() {
+        let mut detector = detector(1);
+        assert!(detector.push(&solid(10)));
+        assert!(detector.push(&solid(200)));
+    }
+}
@@ -0,0 +1,579 @@
+use std::{collections::BTreeMap, fs, io, path::{Path, PathBuf}};
+
+use configparser::ini::Ini;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use crate::{capture::SupportedCaptureSources, engine::SupportedEngines, positioning_structs::OverlayLayout, preprocessing::{self, PreprocessingPipeline}, profiles::{self, Profile}, supported_languages::SupportedLanguages};
+
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+/// The config file used before settings moved to TOML, which is migrated the first time the overlay runs.
+const LEGACY_CONFIG_FILE_NAME: &str = "config.ini";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to access {}: {source}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("Invalid config in {}: {source}", .path.display())]
+    Parse { path: PathBuf, source: toml::de::Error },
+    #[error("Failed to read the old config {}: {message}", .path.display())]
+    Legacy { path: PathBuf, message: String },
+    #[error("Failed to save the config: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Invalid value for {key}: {message}")]
+    Invalid { key: String, message: String },
+    #[error("No profile named {name}, expected one of: {available}")]
+    UnknownProfile { name: String, available: String },
+}
+
+/// Every setting the overlay reads, stored as TOML with defaults filled in for anything which is missing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub screen: ScreenConfig,
+    pub other: OtherConfig,
+    pub debug: DebugConfig,
+    pub capture: CaptureConfig,
+    pub watch: WatchConfig,
+    pub stability: StabilityConfig,
+    pub cache: CacheConfig,
+    pub corrections: CorrectionsConfig,
+    pub dictionary: DictionaryConfig,
+    /// Preprocessing pipelines by name, such as `default = "resize(4, catmullrom), blur(0.9), dither"`.
+    pub preprocessing: BTreeMap<String, String>,
+    pub profiles: ProfilesConfig,
+    pub profile: BTreeMap<String, Profile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            screen: ScreenConfig::default(),
+            other: OtherConfig::default(),
+            debug: DebugConfig::default(),
+            capture: CaptureConfig::default(),
+            watch: WatchConfig::default(),
+            stability: StabilityConfig::default(),
+            cache: CacheConfig::default(),
+            corrections: CorrectionsConfig::default(),
+            dictionary: DictionaryConfig::default(),
+            preprocessing: BTreeMap::from([(preprocessing::DEFAULT_PROFILE.to_string(), PreprocessingPipeline::default().to_string())]),
+            profiles: ProfilesConfig::default(),
+            profile: BTreeMap::new(),
+        }
+    }
+}
+
+/// Where the overlay window was last placed, which is also the area of the screen that gets captured.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScreenConfig {
+    pub x_pos: i32,
+    pub y_pos: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for ScreenConfig {
+    fn default() -> Self {
+        Self {
+            x_pos: 100,
+            y_pos: 100,
+            width: 100,
+            height: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtherConfig {
+    pub language: SupportedLanguages,
+    pub engine: SupportedEngines,
+    pub layout: OverlayLayout,
+    pub preprocessing_profile: String,
+    pub auto_invert: bool,
+}
+
+impl Default for OtherConfig {
+    fn default() -> Self {
+        Self {
+            language: SupportedLanguages::ChiTra,
+            engine: SupportedEngines::Tesseract,
+            layout: OverlayLayout::InPlace,
+            preprocessing_profile: preprocessing::DEFAULT_PROFILE.to_string(),
+            auto_invert: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebugConfig {
+    pub dump_stages: bool,
+    pub dump_dir: PathBuf,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            dump_stages: false,
+            dump_dir: PathBuf::from("ocr_debug"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    pub source: SupportedCaptureSources,
+    /// The image, stream or directory of frames to read from, depending on the source.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Frame size of a raw RGBA stream.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            source: SupportedCaptureSources::Screen,
+            path: None,
+            width: None,
+            height: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchConfig {
+    pub enabled: bool,
+    pub interval_ms: u64,
+    pub threshold: f32,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_ms: 500,
+            threshold: 0.02,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StabilityConfig {
+    pub enabled: bool,
+    pub stable_frames: u32,
+    pub interval_ms: u64,
+    pub threshold: f32,
+    pub max_wait_ms: u64,
+}
+
+impl Default for StabilityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            stable_frames: 3,
+            interval_ms: 100,
+            threshold: 0.005,
+            max_wait_ms: 3000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// How many recent scans are remembered, where 0 turns the cache off.
+    pub capacity: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorrectionsConfig {
+    pub record: bool,
+    pub dir: PathBuf,
+}
+
+impl Default for CorrectionsConfig {
+    fn default() -> Self {
+        Self {
+            record: true,
+            dir: PathBuf::from("corrections"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DictionaryConfig {
+    pub user_dictionary: PathBuf,
+    /// EDICT format dictionary used for Japanese, such as edict2u.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub japanese: Option<PathBuf>,
+    /// CC-CEDICT or EDICT format dictionary used for Korean.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub korean: Option<PathBuf>,
+    /// CC-Canto format dictionary used for Cantonese, looked up before CC-CEDICT.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cantonese: Option<PathBuf>,
+}
+
+impl Default for DictionaryConfig {
+    fn default() -> Self {
+        Self {
+            user_dictionary: PathBuf::from("user_dictionary.txt"),
+            japanese: None,
+            korean: None,
+            cantonese: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfilesConfig {
+    /// The profile which was in use when the overlay was last closed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active: Option<String>,
+}
+
+impl Config {
+    /// `decky_translate/config.toml` in the platform's config directory, such as `$XDG_CONFIG_HOME` on Linux,
+    /// or the working directory when there isn't one.
+    pub fn default_path() -> PathBuf {
+        match dirs::config_dir() {
+            Some(directory) => directory.join("decky_translate").join(CONFIG_FILE_NAME),
+            None => PathBuf::from(CONFIG_FILE_NAME),
+        }
+    }
+
+    /// Load the config from the given file.
+    ///
+    /// When the file doesn't exist yet, an old `config.ini` next to it or in the working directory is migrated and
+    /// saved in its place, and otherwise the defaults are used.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let config = match fs::read_to_string(path) {
+            Ok(text) => toml::from_str::<Self>(&text).map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let legacy_paths = [path.with_file_name(LEGACY_CONFIG_FILE_NAME), PathBuf::from(LEGACY_CONFIG_FILE_NAME)];
+                match legacy_paths.iter().find(|legacy_path| legacy_path.is_file()) {
+                    Some(legacy_path) => {
+                        let config = Self::from_legacy(legacy_path)?;
+                        config.validate()?;
+                        config.save(path)?;
+                        log::info!("Migrated {} to {}", legacy_path.display(), path.display());
+                        config
+                    },
+                    None => Self::default(),
+                }
+            },
+            Err(source) => return Err(ConfigError::Io { path: path.to_path_buf(), source }),
+        };
+        config.validate()?;
+        return Ok(config);
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+            fs::create_dir_all(directory).map_err(|source| ConfigError::Io { path: directory.to_path_buf(), source })?;
+        }
+        fs::write(path, toml::to_string_pretty(self)?).map_err(|source| ConfigError::Io { path: path.to_path_buf(), source })?;
+        return Ok(());
+    }
+
+    /// Read an old `config.ini`, whose sections and keys match the TOML layout apart from the values being untyped.
+    ///
+    /// Values such as `language="ChiTra"` were stored as JSON, so anything which parses as JSON keeps its type and
+    /// everything else becomes a string. `[profile.<name>]` sections become tables within `profile`.
+    /// Section and key names are case insensitive as they were in the INI file, except for the names of profiles and
+    /// preprocessing pipelines, which keep their case since they are looked up by name.
+    fn from_legacy(path: &Path) -> Result<Self, ConfigError> {
+        let mut config_parser = Ini::new_cs();
+        config_parser.load(path).map_err(|message| ConfigError::Legacy { path: path.to_path_buf(), message })?;
+        let mut table = toml::value::Table::new();
+        for (section, values) in config_parser.get_map_ref() {
+            let section = match section.split_once('.') {
+                Some((parent, child)) => format!("{}.{}", parent.to_lowercase(), child),
+                None => section.to_lowercase(),
+            };
+            let mut section_table = toml::value::Table::new();
+            for (key, value) in values {
+                let key = match section.as_str() {
+                    "preprocessing" => key.clone(),
+                    _ => key.to_lowercase(),
+                };
+                if let Some(value) = value {
+                    section_table.insert(key, legacy_value(value));
+                }
+            }
+            if section_table.is_empty() {
+                continue;
+            }
+            match section.split_once('.') {
+                Some((parent, child)) => {
+                    let parent_table = table.entry(parent.to_string())
+                        .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+                    if let toml::Value::Table(parent_table) = parent_table {
+                        parent_table.insert(child.to_string(), toml::Value::Table(section_table));
+                    }
+                },
+                None => {
+                    table.insert(section, toml::Value::Table(section_table));
+                },
+            }
+        }
+        return toml::Value::Table(table).try_into::<Self>()
+            .map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source });
+    }
+
+    /// Check the settings which can be parsed but still make no sense, naming the key at fault.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &str, message: &str| -> Result<(), ConfigError> {
+            Err(ConfigError::Invalid { key: key.to_string(), message: message.to_string() })
+        };
+        if self.screen.width == 0 {
+            return invalid("screen.width", "the window must be at least 1 pixel wide");
+        }
+        if self.screen.height == 0 {
+            return invalid("screen.height", "the window must be at least 1 pixel high");
+        }
+        for (name, steps) in &self.preprocessing {
+            if let Err(e) = PreprocessingPipeline::parse(steps) {
+                return invalid(&format!("preprocessing.{}", name), &e.to_string());
+            }
+        }
+        self.validate_preprocessing_profile("other.preprocessing_profile", &self.other.preprocessing_profile)?;
+        match self.capture.source {
+            SupportedCaptureSources::Screen | SupportedCaptureSources::Portal => {},
+            SupportedCaptureSources::File | SupportedCaptureSources::Replay if self.capture.path.is_none() => {
+                return invalid("capture.path", "required by this capture source");
+            },
+            SupportedCaptureSources::Stream => {
+                if self.capture.path.is_none() {
+                    return invalid("capture.path", "required by the Stream capture source, use \"-\" for stdin");
+                }
+                if self.capture.width.unwrap_or(0) == 0 {
+                    return invalid("capture.width", "the frame width is required by the Stream capture source");
+                }
+                if self.capture.height.unwrap_or(0) == 0 {
+                    return invalid("capture.height", "the frame height is required by the Stream capture source");
+                }
+            },
+            SupportedCaptureSources::File | SupportedCaptureSources::Replay => {},
+        }
+        if self.watch.interval_ms == 0 {
+            return invalid("watch.interval_ms", "must be at least 1");
+        }
+        if !(0.0 ..= 1.0).contains(&self.watch.threshold) {
+            return invalid("watch.threshold", "must be between 0 and 1");
+        }
+        if self.stability.stable_frames == 0 {
+            return invalid("stability.stable_frames", "must be at least 1");
+        }
+        if self.stability.interval_ms == 0 {
+            return invalid("stability.interval_ms", "must be at least 1");
+        }
+        if !(0.0 ..= 1.0).contains(&self.stability.threshold) {
+            return invalid("stability.threshold", "must be between 0 and 1");
+        }
+        for (name, profile) in &self.profile {
+            if let Some(preprocessing_profile) = &profile.preprocessing_profile {
+                self.validate_preprocessing_profile(&format!("profile.{}.preprocessing_profile", name), preprocessing_profile)?;
+            }
+            if profile.width == Some(0) {
+                return invalid(&format!("profile.{}.width", name), "the window must be at least 1 pixel wide");
+            }
+            if profile.height == Some(0) {
+                return invalid(&format!("profile.{}.height", name), "the window must be at least 1 pixel high");
+            }
+            if let Some(hotkey) = &profile.hotkey {
+                if profiles::parse_hotkey(hotkey).is_none() {
+                    return invalid(&format!("profile.{}.hotkey", name), "expected a function key other than F2 or F3, such as \"F5\"");
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn validate_preprocessing_profile(&self, key: &str, preprocessing_profile: &str) -> Result<(), ConfigError> {
+        if preprocessing_profile == preprocessing::DEFAULT_PROFILE || self.preprocessing.contains_key(preprocessing_profile) {
+            return Ok(());
+        }
+        return Err(ConfigError::Invalid {
+            key: key.to_string(),
+            message: format!("there is no pipeline named {:?} in the preprocessing table", preprocessing_profile),
+        });
+    }
+
+    /// The preprocessing pipeline selected by `other.preprocessing_profile`, which has already been validated.
+    pub fn get_pipeline(&self) -> PreprocessingPipeline {
+        let pipeline = match self.preprocessing.get(&self.other.preprocessing_profile) {
+            Some(steps) => PreprocessingPipeline::parse(steps).unwrap_or_default(),
+            None => PreprocessingPipeline::default(),
+        };
+        return pipeline.with_auto_invert(self.other.auto_invert);
+    }
+
+    /// The window rectangle for the given profile, falling back to the shared one until the profile has been used.
+    pub fn get_screen(&self, profile: Option<&str>) -> ScreenConfig {
+        profile.and_then(|profile| self.profile.get(profile))
+            .and_then(Profile::get_screen)
+            .unwrap_or(self.screen)
+    }
+
+    /// Remember the window rectangle, in the active profile when there is one so every game keeps its own.
+    pub fn set_screen(&mut self, profile: Option<&str>, screen: ScreenConfig) {
+        match profile.and_then(|profile| self.profile.get_mut(profile)) {
+            Some(profile) => profile.set_screen(screen),
+            None => self.screen = screen,
+        }
+    }
+}
+
+fn legacy_value(value: &str) -> toml::Value {
+    match serde_json::from_str::<serde_json::Value>(value) {
+        Ok(serde_json::Value::Bool(value)) => toml::Value::Boolean(value),
+        Ok(serde_json::Value::Number(number)) => match number.as_i64() {
+            Some(integer) => toml::Value::Integer(integer),
+            None => toml::Value::Float(number.as_f64().unwrap_or_default()),
+        },
+        Ok(serde_json::Value::String(string)) => toml::Value::String(string),
+        _ => toml::Value::String(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    fn invalid_key(text: &str) -> String {
+        match parse(text).validate() {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            other => panic!("Expected an invalid value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(Config::default().validate().is_ok());
+        assert!(parse(&toml::to_string_pretty(&Config::default()).unwrap()).validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(toml::from_str::<Config>("[screen]\nwidht = 5").is_err());
+        assert!(toml::from_str::<Config>("[colour]\nred = 1").is_err());
+    }
+
+    #[test]
+    fn names_the_exact_key() {
+        assert_eq!(invalid_key("[screen]\nwidth = 0"), "screen.width");
+        assert_eq!(invalid_key("[screen]\nheight = 0"), "screen.height");
+        assert_eq!(invalid_key("[watch]\ninterval_ms = 0"), "watch.interval_ms");
+        assert_eq!(invalid_key("[watch]\nthreshold = 1.5"), "watch.threshold");
+        assert_eq!(invalid_key("[stability]\nstable_frames = 0"), "stability.stable_frames");
+        assert_eq!(invalid_key("[stability]\ninterval_ms = 0"), "stability.interval_ms");
+        assert_eq!(invalid_key("[stability]\nthreshold = -0.1"), "stability.threshold");
+        assert_eq!(invalid_key("[capture]\nsource = \"Stream\"\npath = \"-\"\nwidth = 640"), "capture.height");
+        assert_eq!(invalid_key("[other]\npreprocessing_profile = \"missing\""), "other.preprocessing_profile");
+    }
+
+    #[test]
+    fn rejects_non_positive_preprocessing_arguments() {
+        assert_eq!(invalid_key("[preprocessing]\ndefault = \"resize(0), otsu\""), "preprocessing.default");
+        assert_eq!(invalid_key("[preprocessing]\ndefault = \"resize(-2), otsu\""), "preprocessing.default");
+        assert_eq!(invalid_key("[preprocessing]\nsoft = \"blur(-0.5)\""), "preprocessing.soft");
+        assert_eq!(invalid_key("[preprocessing]\nsoft = \"blur(0)\""), "preprocessing.soft");
+        assert!(parse("[preprocessing]\ndefault = \"resize(2.5), blur(0.5), otsu\"").validate().is_ok());
+    }
+
+    #[test]
+    fn error_messages_name_the_key_and_argument() {
+        let message = |text: &str| parse(text).validate().unwrap_err().to_string();
+        assert_eq!(message("[preprocessing]\ndefault = \"resize(0), otsu\""),
+            "Invalid value for preprocessing.default: Invalid argument \"0\" for preprocessing step \"resize\"");
+        assert_eq!(message("[preprocessing]\nsoft = \"blur(-1)\""),
+            "Invalid value for preprocessing.soft: Invalid argument \"-1\" for preprocessing step \"blur\"");
+        assert_eq!(message("[watch]\ninterval_ms = 0"), "Invalid value for watch.interval_ms: must be at least 1");
+    }
+
+    #[test]
+    fn names_the_profile_at_fault() {
+        assert_eq!(invalid_key("[profile.Persona5]\nwidth = 0"), "profile.Persona5.width");
+        assert_eq!(invalid_key("[profile.Persona5]\nheight = 0"), "profile.Persona5.height");
+        assert_eq!(invalid_key("[profile.Persona5]\nhotkey = \"F2\""), "profile.Persona5.hotkey");
+        assert_eq!(invalid_key("[profile.Persona5]\npreprocessing_profile = \"missing\""), "profile.Persona5.preprocessing_profile");
+    }
+
+    #[test]
+    fn migrates_legacy_config() {
+        let directory = std::env::temp_dir().join(format!("decky_translate_legacy_config_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join(LEGACY_CONFIG_FILE_NAME), "\
+[Screen]
+x_pos=10
+y_pos=20
+width=300
+height=120
+
+[other]
+language=\"ChiSim\"
+layout=\"Outline\"
+auto_invert=false
+
+[watch]
+enabled=true
+threshold=0.05
+
+[preprocessing]
+Subtitles=\"resize(4), otsu\"
+
+[profile.Persona5]
+language=\"Jpn\"
+hotkey=\"F5\"
+preprocessing_profile=\"Subtitles\"
+").unwrap();
+        let path = directory.join(CONFIG_FILE_NAME);
+        let config = Config::load(&path);
+        let saved = fs::read_to_string(&path);
+        fs::remove_dir_all(&directory).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.screen, ScreenConfig { x_pos: 10, y_pos: 20, width: 300, height: 120 });
+        assert_eq!(config.other.language, SupportedLanguages::ChiSim);
+        assert_eq!(config.other.layout, OverlayLayout::Outline);
+        assert!(!config.other.auto_invert);
+        assert!(config.watch.enabled);
+        assert_eq!(config.watch.threshold, 0.05);
+        assert_eq!(config.watch.interval_ms, WatchConfig::default().interval_ms);
+        assert!(config.preprocessing.contains_key("Subtitles"));
+        let profile = &config.profile["Persona5"];
+        assert_eq!(profile.language, Some(SupportedLanguages::Jpn));
+        assert_eq!(profile.hotkey.as_deref(), Some("F5"));
+        assert_eq!(profile.preprocessing_profile.as_deref(), Some("Subtitles"));
+
+        let saved = parse(&saved.unwrap());
+        assert_eq!(saved.screen, config.screen);
+        assert!(saved.profile.contains_key("Persona5"));
+    }
+}
@@ -0,0 +1,81 @@
+use std::{fs::{self, OpenOptions}, io::{self, Write}, path::{Path, PathBuf}};
+
+use image::DynamicImage;
+use serde::Serialize;
+
+use crate::debug_dump;
+
+const LOG_FILE_NAME: &str = "corrections.jsonl";
+
+#[derive(Debug, Serialize)]
+struct CorrectionRecord<'a> {
+    image: &'a str,
+    original: &'a str,
+    corrected: &'a str,
+}
+
+/// Saves lines the user has corrected by hand, as a crop of the captured line and the text it should have been
+/// recognized as, so they can be used as training data later.
+///
+/// Each crop is written as its own PNG, and `corrections.jsonl` gets one line of JSON per correction.
+#[derive(Debug, Clone)]
+pub struct CorrectionLog {
+    directory: PathBuf,
+}
+
+impl CorrectionLog {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+        }
+    }
+
+    pub fn get_directory(&self) -> &Path {
+        return &self.directory;
+    }
+
+    pub fn record(&self, crop: &DynamicImage, original: &str, corrected: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        let image_path = debug_dump::unique_timestamped_path(&self.directory, ".png");
+        crop.save(&image_path).map_err(io::Error::other)?;
+        let image_name = image_path.file_name().unwrap_or_default().to_string_lossy();
+        let record = CorrectionRecord {
+            image: &image_name,
+            original,
+            corrected,
+        };
+        let mut log = OpenOptions::new().create(true).append(true).open(self.directory.join(LOG_FILE_NAME))?;
+        writeln!(log, "{}", serde_json::to_string(&record)?)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn saves_each_crop_and_appends_a_line_of_json() {
+        let directory = std::env::temp_dir().join(format!("decky_translate_corrections_{}", std::process::id()));
+        let correction_log = CorrectionLog::new(directory.join("corrections"));
+        let crop = DynamicImage::ImageLuma8(GrayImage::from_pixel(12, 5, Luma([128])));
+        correction_log.record(&crop, "你妤", "你好").unwrap();
+        correction_log.record(&crop, "\"引号\"", "引号").unwrap();
+
+        let log = fs::read_to_string(correction_log.get_directory().join(LOG_FILE_NAME)).unwrap();
+        let records: Vec<serde_json::Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let crops: Vec<DynamicImage> = records.iter()
+            .map(|record| image::open(correction_log.get_directory().join(record["image"].as_str().unwrap())).unwrap())
+            .collect();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0]["original"].as_str(), records[0]["corrected"].as_str()), (Some("你妤"), Some("你好")));
+        assert_eq!((records[1]["original"].as_str(), records[1]["corrected"].as_str()), (Some("\"引号\""), Some("引号")));
+        assert_ne!(records[0]["image"], records[1]["image"]);
+        for saved_crop in crops {
+            assert_eq!(saved_crop.to_luma8(), crop.to_luma8());
+        }
+    }
+}
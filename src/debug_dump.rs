@@ -0,0 +1,54 @@
+use std::{fs, io, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+use image::DynamicImage;
+
+/// Writes every stage of a single scan into its own timestamped directory, so failed scans can be attached to bug reports
+/// and the raw capture replayed through batch mode.
+pub struct DebugDump {
+    directory: PathBuf,
+    stage: u32,
+}
+
+impl DebugDump {
+    pub fn create(root: &Path) -> io::Result<Self> {
+        let directory = unique_timestamped_path(root, "");
+        fs::create_dir_all(&directory)?;
+        return Ok(Self {
+            directory,
+            stage: 0,
+        });
+    }
+
+    pub fn get_directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Save an image as the next numbered stage, such as `02_blur.png`.
+    pub fn save_image(&mut self, name: &str, image: &DynamicImage) {
+        let path = self.directory.join(format!("{:02}_{}.png", self.stage, name));
+        self.stage += 1;
+        if let Err(e) = image.save(&path) {
+            log::error!("Failed to write debug image {}: {}", path.display(), e);
+        }
+    }
+
+    pub fn save_text(&self, file_name: &str, text: &str) {
+        let path = self.directory.join(file_name);
+        if let Err(e) = fs::write(&path, text) {
+            log::error!("Failed to write debug file {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// A path inside `directory` named after the current time in milliseconds, such as `1681234567890.png` for the
+/// extension `.png`, with a counter appended if something was already saved there within the same millisecond.
+pub fn unique_timestamped_path(directory: &Path, extension: &str) -> PathBuf {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let mut path = directory.join(format!("{}{}", timestamp, extension));
+    let mut suffix = 1;
+    while path.exists() {
+        path = directory.join(format!("{}_{}{}", timestamp, suffix, extension));
+        suffix += 1;
+    }
+    return path;
+}
@@ -0,0 +1,541 @@
+use std::{collections::HashMap, fs, io, path::{Path, PathBuf}, sync::RwLock, thread, time::{Duration, SystemTime}};
+
+use chinese_dictionary::{query_by_chinese, query_by_simplified, query_by_traditional};
+use thiserror::Error;
+
+use crate::supported_languages::Script;
+
+/// How often the user dictionary file is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+static USER_DICTIONARY: RwLock<Option<DictionaryFile>> = RwLock::new(None);
+static JAPANESE_DICTIONARY: RwLock<Option<DictionaryFile>> = RwLock::new(None);
+static KOREAN_DICTIONARY: RwLock<Option<DictionaryFile>> = RwLock::new(None);
+static CANTONESE_DICTIONARY: RwLock<Option<DictionaryFile>> = RwLock::new(None);
+static WATCHED_FILE: RwLock<Option<(PathBuf, Script)>> = RwLock::new(None);
+
+#[derive(Debug, Error)]
+pub enum DictionaryError {
+    #[error("Failed to read the dictionary: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// A dictionary entry from the user dictionary, CC-CEDICT or a language's dictionary file.
+/// Entries for languages other than Chinese have the same headword in both scripts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DictionaryEntry {
+    traditional: String,
+    simplified: String,
+    reading: String,
+    english: Vec<String>,
+}
+
+impl DictionaryEntry {
+    pub fn get_traditional(&self) -> &String {
+        &self.traditional
+    }
+
+    pub fn get_simplified(&self) -> &String {
+        &self.simplified
+    }
+
+    /// Pinyin with tone marks, such as "nǐ hǎo", Jyutping such as "nei5 hou2" for Cantonese, or the kana or romanized
+    /// reading in other languages.
+    pub fn get_reading(&self) -> &String {
+        &self.reading
+    }
+
+    pub fn get_english(&self) -> &Vec<String> {
+        &self.english
+    }
+}
+
+/// Words loaded from a file, such as the user dictionary of character names and place names missing from CC-CEDICT,
+/// or the dictionary for a language without one built in.
+///
+/// The file uses the CC-CEDICT format, with one `traditional simplified [pin1 yin1] /gloss/gloss/` entry per line,
+/// and lines starting with `#` are ignored. EDICT files, with entries such as `日本;日の本 [にほん;ひのもと] /Japan/`,
+/// can also be read, where each spelling separated by `;` is indexed and words written in kana may leave out the reading.
+/// Numbered pinyin such as `hao3` is only converted to tone marks in Chinese dictionaries, so other readings are kept
+/// as they were written. CC-Canto entries, such as `冇 冇 [mao3] {mou5} /not have/`, add the Jyutping in braces,
+/// which is used as the reading in Cantonese dictionaries and ignored in others.
+#[derive(Debug, Default)]
+pub struct DictionaryFile {
+    entries: Vec<DictionaryEntry>,
+    index: HashMap<String, Vec<usize>>, //Both scripts map to the same entries
+    max_length: usize,
+}
+
+impl DictionaryFile {
+    pub fn load(path: &Path, script: Script) -> Result<Self, DictionaryError> {
+        return Ok(Self::parse(&fs::read_to_string(path)?, script));
+    }
+
+    /// Parse the text of a dictionary file, skipping any lines which aren't valid entries with a warning,
+    /// so one typo in a hand written file doesn't lose every other word.
+    pub fn parse(text: &str, script: Script) -> Self {
+        let mut dictionary = Self::default();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match parse_entry(line, script) {
+                Some(entry) => dictionary.insert(entry),
+                None => log::warn!("Skipping invalid dictionary entry on line {}: {}", line_number + 1, line),
+            }
+        }
+        return dictionary;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn query(&self, text: &str) -> Vec<&DictionaryEntry> {
+        match self.index.get(text) {
+            Some(indices) => indices.iter().map(|index| &self.entries[*index]).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn contains(&self, text: &str) -> bool {
+        self.index.contains_key(text)
+    }
+
+    fn insert(&mut self, entry: DictionaryEntry) {
+        let index = self.entries.len();
+        let mut headwords: Vec<&str> = entry.traditional.split(';').chain(entry.simplified.split(';')).collect();
+        headwords.sort();
+        headwords.dedup();
+        for headword in headwords {
+            self.max_length = self.max_length.max(headword.chars().count());
+            self.index.entry(headword.to_string()).or_default().push(index);
+        }
+        self.entries.push(entry);
+    }
+
+    /// The longest word at the start of the given characters, as a number of characters.
+    fn longest_match(&self, characters: &[char]) -> Option<usize> {
+        (1 ..= self.max_length.min(characters.len())).rev()
+            .find(|length| self.index.contains_key(&characters[.. *length].iter().collect::<String>()))
+    }
+}
+
+/// Replace the user dictionary used by every lookup, or remove it with `None`.
+pub fn set_user_dictionary(dictionary: Option<DictionaryFile>) {
+    *USER_DICTIONARY.write().unwrap() = dictionary;
+}
+
+/// Replace the dictionary for a language without one built in, such as JMdict converted to EDICT for Japanese,
+/// or CC-Canto for Cantonese, which is looked up before falling back to the built in CC-CEDICT.
+/// Chinese only uses CC-CEDICT.
+pub fn set_language_dictionary(script: Script, dictionary: Option<DictionaryFile>) {
+    match language_dictionary(script) {
+        Some(language_dictionary) => *language_dictionary.write().unwrap() = dictionary,
+        None => log::warn!("{:?} uses the built in dictionary, so the dictionary file was ignored", script),
+    }
+}
+
+fn language_dictionary(script: Script) -> Option<&'static RwLock<Option<DictionaryFile>>> {
+    match script {
+        Script::Chinese => None,
+        Script::Japanese => Some(&JAPANESE_DICTIONARY),
+        Script::Korean => Some(&KOREAN_DICTIONARY),
+        Script::Cantonese => Some(&CANTONESE_DICTIONARY),
+    }
+}
+
+/// Holds every test which uses the process wide dictionaries, since tests run in parallel.
+#[cfg(test)]
+static TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Empties the process wide dictionaries for the length of a test, so tests which set them don't see each other's
+/// words, and puts the previous dictionaries back when dropped.
+#[cfg(test)]
+pub(crate) struct IsolatedDictionaries {
+    previous: Vec<(&'static RwLock<Option<DictionaryFile>>, Option<DictionaryFile>)>,
+    _lock: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl IsolatedDictionaries {
+    pub(crate) fn new() -> Self {
+        let lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner()); //One failed test shouldn't fail the rest
+        let previous = [&USER_DICTIONARY, &JAPANESE_DICTIONARY, &KOREAN_DICTIONARY, &CANTONESE_DICTIONARY].into_iter()
+            .map(|dictionary| (dictionary, dictionary.write().unwrap().take()))
+            .collect();
+        Self {
+            previous,
+            _lock: lock,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Drop for IsolatedDictionaries {
+    fn drop(&mut self) {
+        for (dictionary, previous) in self.previous.drain(..) {
+            *dictionary.write().unwrap() = previous;
+        }
+    }
+}
+
+/// Load the user dictionary from the given file, then keep reloading it in the background whenever it changes.
+///
+/// Calling this again switches the watched file, such as when the overlay changes profile. A missing file is treated
+/// as an empty dictionary, and a file which can't be read leaves the previous entries in place.
+/// Readings are parsed for the given script, which is the language being read.
+pub fn watch_user_dictionary(path: PathBuf, script: Script) {
+    let is_watching = WATCHED_FILE.read().unwrap().is_some();
+    if reload(&path, script, None).is_none() {
+        set_user_dictionary(None); //Don't keep the words from a previous file
+    }
+    *WATCHED_FILE.write().unwrap() = Some((path.clone(), script));
+    if is_watching {
+        return;
+    }
+    thread::spawn(move || {
+        let mut watched_file = (path, script);
+        let mut last_modified = fs::metadata(&watched_file.0).and_then(|metadata| metadata.modified()).ok();
+        loop {
+            thread::sleep(RELOAD_INTERVAL);
+            let current_file = WATCHED_FILE.read().unwrap().clone().unwrap();
+            if current_file != watched_file { //Already loaded when the file was switched
+                watched_file = current_file;
+                last_modified = fs::metadata(&watched_file.0).and_then(|metadata| metadata.modified()).ok();
+                continue;
+            }
+            last_modified = reload(&watched_file.0, watched_file.1, last_modified);
+        }
+    });
+}
+
+/// Reload the dictionary if the file was modified since `last_modified`, returning its new modification time.
+fn reload(path: &Path, script: Script, last_modified: Option<SystemTime>) -> Option<SystemTime> {
+    let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    if modified == last_modified {
+        return last_modified;
+    }
+    match modified {
+        Some(_) => match DictionaryFile::load(path, script) {
+            Ok(dictionary) => {
+                log::info!("Loaded {} user dictionary entries from {}", dictionary.len(), path.display());
+                set_user_dictionary(Some(dictionary));
+            },
+            Err(e) => log::error!("Failed to load user dictionary {}: {}", path.display(), e),
+        },
+        None => set_user_dictionary(None),
+    }
+    return modified;
+}
+
+/// Entries for the given word, with user dictionary entries first.
+///
+/// Korean words are followed by particles and verb endings, so when a Korean word isn't found the longest prefix of it
+/// which is a word is looked up instead.
+pub fn query(script: Script, text: &str) -> Vec<DictionaryEntry> {
+    let entries = query_exact(script, text);
+    if !entries.is_empty() || script != Script::Korean {
+        return entries;
+    }
+    let characters: Vec<char> = text.chars().collect();
+    return (1 .. characters.len()).rev()
+        .map(|length| query_exact(script, &characters[.. length].iter().collect::<String>()))
+        .find(|entries| !entries.is_empty())
+        .unwrap_or_default();
+}
+
+fn query_exact(script: Script, text: &str) -> Vec<DictionaryEntry> {
+    let mut entries = query_file(&USER_DICTIONARY, text);
+    if let Some(language_dictionary) = language_dictionary(script) {
+        entries.extend(query_file(language_dictionary, text));
+    }
+    if script.is_chinese() {
+        entries.extend(query_by_chinese(text).iter().map(|entry| DictionaryEntry {
+            traditional: entry.traditional.clone(),
+            simplified: entry.simplified.clone(),
+            reading: entry.pinyin_marks.clone(),
+            english: entry.english.clone(),
+        }));
+    }
+    return entries;
+}
+
+fn query_file(dictionary: &RwLock<Option<DictionaryFile>>, text: &str) -> Vec<DictionaryEntry> {
+    match &*dictionary.read().unwrap() {
+        Some(dictionary) => dictionary.query(text).into_iter().cloned().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Whether the text is a word in the user dictionary or the language's dictionary.
+pub fn is_word(script: Script, text: &str) -> bool {
+    let contains = |dictionary: &RwLock<Option<DictionaryFile>>| dictionary.read().unwrap().as_ref()
+        .is_some_and(|dictionary| dictionary.contains(text));
+    return contains(&USER_DICTIONARY)
+        || language_dictionary(script).is_some_and(contains)
+        || script.is_chinese() && (!query_by_simplified(text).is_empty() || !query_by_traditional(text).is_empty()); //query_by_chinese segments the text
+}
+
+/// Split text into words.
+///
+/// Korean is split on spaces. Otherwise the longest word from the user dictionary or the language's dictionary file is
+/// kept whole at each position, and the text between them is tokenized with CC-CEDICT for Chinese and Cantonese or
+/// split into characters for Japanese.
+pub fn tokenize(script: Script, text: &str) -> Vec<String> {
+    if script.has_word_spaces() {
+        return text.split_whitespace().map(String::from).collect();
+    }
+    let user_dictionary = USER_DICTIONARY.read().unwrap();
+    let language_dictionary = language_dictionary(script).map(|language_dictionary| language_dictionary.read().unwrap());
+    let dictionaries: Vec<&DictionaryFile> = [user_dictionary.as_ref(), language_dictionary.as_ref().and_then(|dictionary| dictionary.as_ref())]
+        .into_iter()
+        .flatten()
+        .filter(|dictionary| !dictionary.is_empty())
+        .collect();
+    if dictionaries.is_empty() {
+        return tokenize_unmatched(script, text);
+    }
+    let characters: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut unmatched = String::new();
+    let mut i = 0;
+    while i < characters.len() {
+        match dictionaries.iter().filter_map(|dictionary| dictionary.longest_match(&characters[i ..])).max() {
+            Some(length) => {
+                if !unmatched.is_empty() {
+                    tokens.extend(tokenize_unmatched(script, &unmatched));
+                    unmatched.clear();
+                }
+                tokens.push(characters[i .. i + length].iter().collect());
+                i += length;
+            },
+            None => {
+                unmatched.push(characters[i]);
+                i += 1;
+            },
+        }
+    }
+    if !unmatched.is_empty() {
+        tokens.extend(tokenize_unmatched(script, &unmatched));
+    }
+    return tokens;
+}
+
+fn tokenize_unmatched(script: Script, text: &str) -> Vec<String> {
+    match script {
+        Script::Chinese | Script::Cantonese => chinese_dictionary::tokenize(text).into_iter().map(String::from).collect(),
+        Script::Japanese | Script::Korean => text.chars().map(String::from).collect(),
+    }
+}
+
+fn parse_entry(line: &str, script: Script) -> Option<DictionaryEntry> {
+    let (head, definitions) = line.split_at(line.find('/')?);
+    let (head, jyutping) = match head.split_once('{') {
+        Some((head, rest)) => (head, Some(rest.split_once('}')?.0)),
+        None => (head, None),
+    };
+    let (headwords, reading) = match head.split_once('[') {
+        Some((headwords, rest)) => (headwords, rest.split_once(']')?.0),
+        None => (head, ""), //EDICT leaves out the reading of words written in kana
+    };
+    let headwords = strip_tags(headwords);
+    let mut headwords = headwords.split_whitespace();
+    let traditional = headwords.next()?.to_string();
+    let simplified = headwords.next().map(str::to_string).unwrap_or_else(|| traditional.clone());
+    let english: Vec<String> = definitions.trim().split('/')
+        .map(str::trim)
+        .filter(|definition| !definition.is_empty())
+        .map(str::to_string)
+        .collect();
+    let reading = match (script, jyutping) {
+        (Script::Cantonese, Some(jyutping)) => jyutping.to_string(),
+        _ => strip_tags(reading),
+    };
+    return Some(DictionaryEntry {
+        traditional,
+        simplified,
+        reading: reading.split_whitespace()
+            .map(|syllable| match script {
+                Script::Chinese => numbered_to_marked(syllable),
+                Script::Japanese | Script::Korean | Script::Cantonese => syllable.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        english,
+    });
+}
+
+/// Remove EDICT's bracketed tags from headwords and readings, such as the `(P)` marking common words.
+fn strip_tags(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut depth = 0;
+    for char in text.chars() {
+        match char {
+            '(' => depth += 1,
+            ')' => depth = (depth - 1).max(0),
+            char if depth == 0 => stripped.push(char),
+            _ => {},
+        }
+    }
+    return stripped;
+}
+
+/// Convert a numbered pinyin syllable such as "lu:4" or "hao3" into one with a tone mark.
+fn numbered_to_marked(syllable: &str) -> String {
+    let syllable = syllable.replace("u:", "ü").replace('v', "ü");
+    let (letters, tone) = match syllable.chars().last().and_then(|last| last.to_digit(10)) {
+        Some(tone) => (&syllable[.. syllable.len() - 1], tone as usize),
+        None => return syllable,
+    };
+    if !(1 ..= 4).contains(&tone) {
+        return letters.to_string(); //Neutral tone
+    }
+    //The mark goes on a or e if present, the o of ou, and otherwise the last vowel
+    let lower = letters.to_lowercase();
+    let mark_index = lower.find('a')
+        .or_else(|| lower.find('e'))
+        .or_else(|| lower.find("ou"))
+        .or_else(|| lower.char_indices().rfind(|(_, char)| "iouü".contains(*char)).map(|(index, _)| index));
+    let mark_index = match mark_index {
+        Some(mark_index) => mark_index,
+        None => return letters.to_string(),
+    };
+    return letters.char_indices()
+        .map(|(index, char)| match index == mark_index {
+            true => mark_vowel(char, tone),
+            false => char,
+        })
+        .collect();
+}
+
+fn mark_vowel(vowel: char, tone: usize) -> char {
+    let marks = match vowel {
+        'a' => ['ā', 'á', 'ǎ', 'à'],
+        'e' => ['ē', 'é', 'ě', 'è'],
+        'i' => ['ī', 'í', 'ǐ', 'ì'],
+        'o' => ['ō', 'ó', 'ǒ', 'ò'],
+        'u' => ['ū', 'ú', 'ǔ', 'ù'],
+        'ü' => ['ǖ', 'ǘ', 'ǚ', 'ǜ'],
+        'A' => ['Ā', 'Á', 'Ǎ', 'À'],
+        'E' => ['Ē', 'É', 'Ě', 'È'],
+        'O' => ['Ō', 'Ó', 'Ǒ', 'Ò'],
+        _ => return vowel,
+    };
+    return marks[tone - 1];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cedict_entry() {
+        let entry = parse_entry("艾莉絲 艾莉丝 [Ai4 li4 si1] /Alice (protagonist)/the heroine/", Script::Chinese).unwrap();
+        assert_eq!(entry.get_traditional(), "艾莉絲");
+        assert_eq!(entry.get_simplified(), "艾莉丝");
+        assert_eq!(entry.get_reading(), "Ài lì sī");
+        assert_eq!(entry.get_english(), &vec!["Alice (protagonist)".to_string(), "the heroine".to_string()]);
+    }
+
+    #[test]
+    fn parses_edict_entry() {
+        let entry = parse_entry("日本;日の本(oK) [にほん;ひのもと] /(n) Japan/", Script::Japanese).unwrap();
+        assert_eq!(entry.get_traditional(), "日本;日の本");
+        assert_eq!(entry.get_simplified(), "日本;日の本");
+        assert_eq!(entry.get_reading(), "にほん;ひのもと");
+        assert_eq!(entry.get_english(), &vec!["(n) Japan".to_string()]);
+
+        let dictionary = DictionaryFile::parse("日本;日の本(oK) [にほん;ひのもと] /(n) Japan/\nすし /(n) sushi/", Script::Japanese);
+        assert_eq!(dictionary.query("日の本")[0].get_english(), &vec!["(n) Japan".to_string()]);
+        assert_eq!(dictionary.query("日本").len(), 1);
+        assert_eq!(dictionary.query("すし")[0].get_reading(), "");
+        assert_eq!(dictionary.longest_match(&"日の本です".chars().collect::<Vec<_>>()), Some(3));
+        assert_eq!(dictionary.longest_match(&"日曜".chars().collect::<Vec<_>>()), None);
+    }
+
+    #[test]
+    fn keeps_korean_readings_as_written() {
+        let entry = parse_entry("눈 [nun2] /snow/", Script::Korean).unwrap();
+        assert_eq!(entry.get_traditional(), "눈");
+        assert_eq!(entry.get_simplified(), "눈");
+        assert_eq!(entry.get_reading(), "nun2");
+        assert_eq!(entry.get_english(), &vec!["snow".to_string()]);
+        assert_eq!(parse_entry("눈 [nun2] /snow/", Script::Chinese).unwrap().get_reading(), "nún");
+    }
+
+    #[test]
+    fn converts_numbered_pinyin() {
+        assert_eq!(numbered_to_marked("hao3"), "hǎo");
+        assert_eq!(numbered_to_marked("lu:4"), "lǜ");
+        assert_eq!(numbered_to_marked("gou3"), "gǒu");
+        assert_eq!(numbered_to_marked("dui4"), "duì");
+        assert_eq!(numbered_to_marked("ma5"), "ma");
+        assert_eq!(numbered_to_marked("Xi1"), "Xī");
+    }
+
+    #[test]
+    fn skips_comments_and_malformed_lines() {
+        let dictionary = DictionaryFile::parse("\
+# Traditional Simplified [pin1 yin1] /English/
+
+not an entry
+沒有 没有 [mei2 you3]
+[broken /gloss/
+艾莉絲 艾莉丝 [Ai4 li4 si1] /Alice/
+", Script::Chinese);
+        assert_eq!(dictionary.len(), 1);
+        assert!(dictionary.contains("艾莉絲"));
+        assert!(dictionary.contains("艾莉丝"));
+        assert!(!dictionary.contains("沒有"));
+        assert_eq!(dictionary.query("艾莉丝")[0].get_english(), &vec!["Alice".to_string()]);
+    }
+
+    #[test]
+    fn user_entries_take_priority_over_cedict() {
+        let _dictionaries = IsolatedDictionaries::new();
+        assert_eq!(query(Script::Chinese, "你好")[0].get_english(), &vec!["hello".to_string(), "hi".to_string()]);
+        set_user_dictionary(Some(DictionaryFile::parse("\
+你好 你好 [ni3 hao3] /hi there (user)/
+艾莉絲 艾莉丝 [Ai4 li4 si1] /Alice/
+", Script::Chinese)));
+        let entries = query(Script::Chinese, "你好");
+        assert!(entries.len() > 1);
+        assert_eq!(entries[0].get_english(), &vec!["hi there (user)".to_string()]);
+        assert!(is_word(Script::Chinese, "艾莉丝"));
+        assert_eq!(tokenize(Script::Chinese, "艾莉丝你好"), vec!["艾莉丝", "你好"]);
+    }
+
+    #[test]
+    fn reads_jyutping_from_cc_canto_entries() {
+        let entry = parse_entry("冇 冇 [mao3] {mou5} /not have/no/", Script::Cantonese).unwrap();
+        assert_eq!(entry.get_traditional(), "冇");
+        assert_eq!(entry.get_reading(), "mou5");
+        assert_eq!(entry.get_english(), &vec!["not have".to_string(), "no".to_string()]);
+        assert_eq!(parse_entry("冇 冇 [mao3] {mou5} /not have/", Script::Chinese).unwrap().get_reading(), "mǎo");
+        assert_eq!(parse_entry("佢 佢 [qu2] /he/", Script::Cantonese).unwrap().get_reading(), "qu2");
+        assert!(parse_entry("冇 冇 [mao3] {mou5 /not have/", Script::Cantonese).is_none());
+    }
+
+    #[test]
+    fn cantonese_entries_come_before_cedict() {
+        let _dictionaries = IsolatedDictionaries::new();
+        set_language_dictionary(Script::Cantonese, Some(DictionaryFile::parse("你好 你好 [ni3 hao3] {nei5 hou2} /hello (Cantonese)/
+佢哋 佢哋 [qu2 di4] {keoi5 dei6} /they/
+", Script::Cantonese)));
+        let entries = query(Script::Cantonese, "你好");
+        assert!(entries.len() > 1);
+        assert_eq!(entries[0].get_reading(), "nei5 hou2");
+        assert_eq!(entries[1].get_reading(), "nǐ hǎo");
+        assert!(is_word(Script::Cantonese, "佢哋"));
+        assert!(is_word(Script::Cantonese, "你好"));
+        assert!(!is_word(Script::Chinese, "佢哋"));
+        assert_eq!(tokenize(Script::Cantonese, "佢哋你好"), vec!["佢哋", "你好"]);
+        assert_eq!(query(Script::Chinese, "你好")[0].get_reading(), "nǐ hǎo");
+    }
+}
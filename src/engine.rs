@@ -0,0 +1,303 @@
+use std::{fmt, mem, time::{Duration, Instant}};
+
+use image::GrayImage;
+use serde::{Serialize, Deserialize};
+
+use crate::{ocr::{OcrLine, OcrError}, positioning_structs::{HocrWord, OcrSymbol, PixelPoint, SymbolChoice}, supported_languages::SupportedLanguages, tesseract_api::{RawSymbol, TesseractApi}};
+
+/// How many alternative readings are kept for each symbol.
+const MAX_CHOICES: usize = 5;
+
+/// An OCR backend which turns a preprocessed image into lines of words.
+///
+/// Engines report bounding boxes in the coordinates of the image they were given,
+/// and return one `HocrWord` per recognized word, before any dictionary tokenization.
+pub trait OcrEngine {
+    fn recognize(&mut self, image: &GrayImage) -> Result<Vec<OcrLine>, OcrError>;
+
+    /// The language being read, which decides how the engine's output is tokenized.
+    fn get_language(&self) -> SupportedLanguages;
+
+    /// Load any models up front, so that the first scan doesn't pay for it.
+    fn initialize(&mut self) -> Result<(), OcrError> {
+        Ok(())
+    }
+
+    fn get_metrics(&self) -> Option<EngineMetrics> {
+        None
+    }
+
+    /// Whether to keep the engine's raw output for the next images, which is only wanted for debug dumps.
+    fn set_keep_raw_output(&mut self, _keep_raw_output: bool) {}
+
+    /// The engine's raw output for the most recent image, such as Tesseract's hOCR, if it was asked to keep it.
+    fn get_last_raw_output(&self) -> Option<&str> {
+        None
+    }
+
+    /// Describes any settings which change the engine's output, such as its language, so cached results from
+    /// differently configured engines are kept apart.
+    fn get_settings(&self) -> String {
+        String::new()
+    }
+}
+
+/// Cumulative time spent initializing an engine versus recognizing images with it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EngineMetrics {
+    initializations: u32,
+    initialization_time: Duration,
+    recognitions: u32,
+    recognition_time: Duration,
+}
+
+impl EngineMetrics {
+    pub fn get_initializations(&self) -> u32 {
+        self.initializations
+    }
+
+    pub fn get_initialization_time(&self) -> Duration {
+        self.initialization_time
+    }
+
+    pub fn get_recognitions(&self) -> u32 {
+        self.recognitions
+    }
+
+    pub fn get_recognition_time(&self) -> Duration {
+        self.recognition_time
+    }
+
+    fn record_initialization(&mut self, elapsed: Duration) {
+        self.initializations += 1;
+        self.initialization_time += elapsed;
+    }
+
+    fn record_recognition(&mut self, elapsed: Duration) {
+        self.recognitions += 1;
+        self.recognition_time += elapsed;
+    }
+}
+
+impl fmt::Display for EngineMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let average_recognition_time = self.recognition_time.checked_div(self.recognitions).unwrap_or_default();
+        write!(
+            f,
+            "{} initialization(s) taking {:?}, {} recognition(s) taking {:?} ({:?} average)",
+            self.initializations, self.initialization_time, self.recognitions, self.recognition_time, average_recognition_time
+        )
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum SupportedEngines {
+    Tesseract,
+}
+
+/// Keeps a single Tesseract instance warm across scans, so traineddata is only loaded once.
+///
+/// Text is read symbol by symbol through Tesseract's result iterator, so every character has its own box,
+/// confidence and alternative readings, rather than relying on how hOCR happens to group characters into words.
+pub struct TesseractEngine {
+    language: SupportedLanguages,
+    tesseract: Option<TesseractApi>,
+    metrics: EngineMetrics,
+    keep_raw_output: bool,
+    last_hocr_text: Option<String>,
+}
+
+impl TesseractEngine {
+    pub fn new(language: SupportedLanguages) -> Self {
+        Self {
+            language,
+            tesseract: None,
+            metrics: EngineMetrics::default(),
+            keep_raw_output: false,
+            last_hocr_text: None,
+        }
+    }
+
+    fn take_tesseract(&mut self) -> Result<TesseractApi, OcrError> {
+        if let Some(tesseract) = self.tesseract.take() {
+            return Ok(tesseract);
+        }
+        let start = Instant::now();
+        let mut tesseract = TesseractApi::new(&self.language.to_string())?;
+        tesseract.set_variable("lstm_choice_mode", "2")?; //Report alternative characters from the LSTM model
+        if self.language.is_vertical() {
+            tesseract.set_vertical_text();
+        }
+        self.metrics.record_initialization(start.elapsed());
+        return Ok(tesseract);
+    }
+}
+
+impl OcrEngine for TesseractEngine {
+    fn recognize(&mut self, image: &GrayImage) -> Result<Vec<OcrLine>, OcrError> {
+        self.last_hocr_text = None;
+        // If anything fails the instance is dropped, and a fresh one is created on the next scan
+        let mut tesseract = self.take_tesseract()?;
+        let start = Instant::now();
+        let symbols = tesseract.recognize(image, MAX_CHOICES)?;
+        self.metrics.record_recognition(start.elapsed());
+        if self.keep_raw_output { //Generating hOCR is a second pass over the results, so it's skipped unless dumping
+            self.last_hocr_text = tesseract.get_hocr_text();
+        }
+        self.tesseract = Some(tesseract);
+        return Ok(symbol_lines(symbols, self.language.get_script().has_word_spaces()));
+    }
+
+    fn get_language(&self) -> SupportedLanguages {
+        self.language
+    }
+
+    fn initialize(&mut self) -> Result<(), OcrError> {
+        let tesseract = self.take_tesseract()?;
+        self.tesseract = Some(tesseract);
+        return Ok(());
+    }
+
+    fn get_metrics(&self) -> Option<EngineMetrics> {
+        Some(self.metrics)
+    }
+
+    fn set_keep_raw_output(&mut self, keep_raw_output: bool) {
+        self.keep_raw_output = keep_raw_output;
+    }
+
+    fn get_last_raw_output(&self) -> Option<&str> {
+        self.last_hocr_text.as_deref()
+    }
+
+    fn get_settings(&self) -> String {
+        format!("tesseract {}", self.language)
+    }
+}
+
+/// Where a symbol falls in the engine's layout, which decides how symbols are grouped into words and lines.
+#[derive(Debug, Clone, Copy, Default)]
+struct SymbolBreaks {
+    is_line_start: bool,
+    is_word_start: bool,
+    /// Whether the symbol's line is read from top to bottom, which is taken from a line's first symbol.
+    is_vertical: bool,
+}
+
+/// Group symbols from the result iterator into untokenized lines, with one `HocrWord` per symbol,
+/// or one per word reported by Tesseract when `keep_words` is set for languages which put spaces between words.
+fn symbol_lines(symbols: Vec<RawSymbol>, keep_words: bool) -> Vec<OcrLine> {
+    group_symbols(symbols.into_iter().map(to_symbol), keep_words)
+}
+
+fn to_symbol(symbol: RawSymbol) -> (OcrSymbol, SymbolBreaks) {
+    let [x0, y0, x1, y1] = symbol.bbox;
+    let choices = symbol.choices.into_iter()
+        .map(|choice| SymbolChoice::new(choice.text, choice.confidence))
+        .collect();
+    let breaks = SymbolBreaks {
+        is_line_start: symbol.is_line_start,
+        is_word_start: symbol.is_word_start,
+        is_vertical: symbol.is_vertical,
+    };
+    return (OcrSymbol::new(symbol.text, PixelPoint::new(x0 as f32, y0 as f32), PixelPoint::new(x1 as f32, y1 as f32), symbol.confidence, choices), breaks);
+}
+
+fn group_symbols<I: IntoIterator<Item = (OcrSymbol, SymbolBreaks)>>(symbols: I, keep_words: bool) -> Vec<OcrLine> {
+    let mut lines = Vec::new();
+    let mut words = Vec::new();
+    let mut is_vertical = false;
+    for (symbol, breaks) in symbols {
+        if breaks.is_line_start {
+            if !words.is_empty() {
+                lines.push(OcrLine::new(mem::take(&mut words)).with_vertical(is_vertical));
+            }
+            is_vertical = breaks.is_vertical;
+        }
+        let is_word_start = breaks.is_line_start || breaks.is_word_start;
+        let word = HocrWord::from_symbol(symbol);
+        match words.pop() {
+            Some(previous) if keep_words && !is_word_start => words.push(previous + &word),
+            Some(previous) => words.extend([previous, word]),
+            None => words.push(word),
+        }
+    }
+    if !words.is_empty() {
+        lines.push(OcrLine::new(words).with_vertical(is_vertical));
+    }
+    return lines;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tesseract_api::RawChoice;
+
+    const LINE_START: SymbolBreaks = SymbolBreaks { is_line_start: true, is_word_start: true, is_vertical: false };
+    const COLUMN_START: SymbolBreaks = SymbolBreaks { is_line_start: true, is_word_start: true, is_vertical: true };
+    const WORD_START: SymbolBreaks = SymbolBreaks { is_line_start: false, is_word_start: true, is_vertical: false };
+    const WITHIN_WORD: SymbolBreaks = SymbolBreaks { is_line_start: false, is_word_start: false, is_vertical: false };
+
+    fn symbol(text: &str, index: usize, breaks: SymbolBreaks) -> (OcrSymbol, SymbolBreaks) {
+        let x = index as f32 * 20.0;
+        (OcrSymbol::new(text.to_string(), PixelPoint::new(x, 0.0), PixelPoint::new(x + 20.0, 20.0), 90.0, Vec::new()), breaks)
+    }
+
+    fn words(lines: &[OcrLine]) -> Vec<Vec<&str>> {
+        lines.iter().map(|line| line.get_words().iter().map(|word| word.get_text().as_str()).collect()).collect()
+    }
+
+    #[test]
+    fn starts_a_new_line_at_each_line_start() {
+        let lines = group_symbols([
+            symbol("你", 0, LINE_START), symbol("好", 1, WITHIN_WORD), symbol("再", 0, LINE_START), symbol("见", 1, WORD_START),
+        ], false);
+        assert_eq!(words(&lines), vec![vec!["你", "好"], vec!["再", "见"]]);
+        assert!(lines.iter().flat_map(|line| line.get_words()).all(|word| word.get_symbols().len() == 1));
+    }
+
+    #[test]
+    fn keeps_engine_words_when_asked() {
+        let symbols = [symbol("사", 0, LINE_START), symbol("람", 1, WITHIN_WORD), symbol("이", 2, WORD_START), symbol("다", 3, LINE_START)];
+        let lines = group_symbols(symbols.clone(), true);
+        assert_eq!(words(&lines), vec![vec!["사람", "이"], vec!["다"]]);
+        assert_eq!(lines[0].get_words()[0].get_symbols().len(), 2);
+        assert_eq!(lines[0].get_words()[0].get_max(), PixelPoint::new(40.0, 20.0));
+        assert_eq!(words(&group_symbols(symbols, false)), vec![vec!["사", "람", "이"], vec!["다"]]);
+    }
+
+    #[test]
+    fn takes_direction_from_the_first_symbol_of_each_line() {
+        let lines = group_symbols([
+            symbol("縦", 0, COLUMN_START), symbol("書", 1, WORD_START), symbol("横", 0, LINE_START), symbol("書", 1, WORD_START),
+        ], false);
+        assert!(lines[0].is_vertical());
+        assert!(!lines[1].is_vertical());
+        assert!(group_symbols(Vec::new(), false).is_empty());
+    }
+
+    #[test]
+    fn converts_raw_symbols_with_and_without_choices() {
+        let raw = |choices: Vec<RawChoice>| RawSymbol {
+            text: "妤".to_string(),
+            bbox: [4, 6, 24, 30],
+            confidence: 40.0,
+            choices,
+            is_line_start: true,
+            is_word_start: true,
+            is_vertical: false,
+        };
+        let (symbol, breaks) = to_symbol(raw(Vec::new()));
+        assert!(symbol.get_choices().is_empty());
+        assert_eq!(symbol.get_min(), PixelPoint::new(4.0, 6.0));
+        assert_eq!(symbol.get_max(), PixelPoint::new(24.0, 30.0));
+        assert!(breaks.is_line_start && !breaks.is_vertical);
+
+        let (symbol, _) = to_symbol(raw(vec![
+            RawChoice { text: "好".to_string(), confidence: 35.0 },
+            RawChoice { text: "奸".to_string(), confidence: 20.0 },
+        ]));
+        let choices = symbol.get_choices().iter().map(|choice| (choice.get_text().as_str(), choice.get_confidence())).collect::<Vec<_>>();
+        assert_eq!(choices, vec![("好", 35.0), ("奸", 20.0)]);
+    }
+}
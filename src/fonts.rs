@@ -0,0 +1,57 @@
+use std::{fs, path::PathBuf};
+
+use glyph_brush::ab_glyph::FontArc;
+
+use crate::config::Config;
+
+/// The font recognized text is drawn with, which isn't bundled since SimHei can't be redistributed.
+pub const FONT_FILE_NAME: &str = "SimHei.ttf";
+
+/// Load `SimHei.ttf` from the config directory or the working directory, which the overlay and replay both lay out
+/// text with.
+///
+/// Without it the bundled Inconsolata is used, which has no Chinese, Japanese or Korean glyphs, so the overlay draws
+/// empty boxes and replay layouts are only approximate, but everything else still works.
+pub fn load_font() -> FontArc {
+    let mut directories = Vec::new();
+    if let Some(directory) = Config::default_path().parent() {
+        directories.push(directory.to_path_buf());
+    }
+    directories.push(PathBuf::from("."));
+    return load_font_from(&directories);
+}
+
+fn load_font_from(directories: &[PathBuf]) -> FontArc {
+    for path in directories.iter().map(|directory| directory.join(FONT_FILE_NAME)) {
+        if let Ok(bytes) = fs::read(&path) {
+            match FontArc::try_from_vec(bytes) {
+                Ok(font) => return font,
+                Err(e) => log::error!("Failed to load the font {}: {}", path.display(), e),
+            }
+        }
+    }
+    log::warn!("{} wasn't found in the config or working directory, so CJK text won't be drawn", FONT_FILE_NAME);
+    return FontArc::try_from_slice(include_bytes!("Inconsolata-Regular.ttf")).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glyph_brush::ab_glyph::Font;
+
+    #[test]
+    fn falls_back_to_the_bundled_font() {
+        let font = load_font_from(&[std::env::temp_dir().join("decky_translate_no_fonts")]);
+        assert_ne!(font.glyph_id('A').0, 0);
+    }
+
+    #[test]
+    fn ignores_files_which_are_not_fonts() {
+        let directory = std::env::temp_dir().join(format!("decky_translate_bad_font_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join(FONT_FILE_NAME), "not a font").unwrap();
+        let font = load_font_from(std::slice::from_ref(&directory));
+        fs::remove_dir_all(&directory).unwrap();
+        assert_ne!(font.glyph_id('A').0, 0);
+    }
+}
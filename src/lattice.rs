@@ -0,0 +1,208 @@
+use crate::{dictionary, positioning_structs::{HocrWord, OcrSymbol}, supported_languages::Script};
+
+/// The longest dictionary word looked for in the lattice.
+const MAX_WORD_LENGTH: usize = 4;
+/// How many readings of each uncertain symbol are considered, including the engine's own pick.
+const MAX_CANDIDATES: usize = 3;
+/// Score added for each character beyond the first in a segment which is a dictionary word.
+const WORD_BONUS: f32 = 1.0;
+/// Confidence floor, so a single zero confidence reading doesn't rule out a whole path.
+const MIN_CONFIDENCE: f32 = 1.0;
+
+/// Pick the readings of a line's uncertain symbols which best combine into dictionary words.
+///
+/// Each symbol's candidates are its own text along with, for symbols below the confidence threshold, the engine's
+/// alternatives. A Viterbi search over every way of splitting the line into segments of up to `MAX_WORD_LENGTH`
+/// symbols then maximizes the total log confidence, plus a bonus for every multi-character segment found in the
+/// dictionary. Symbols whose reading changes remember the engine's original text.
+///
+/// Expects one word per symbol, as returned by the engine before tokenization, except in languages which put spaces
+/// between words, where the engine's words are kept and each is corrected on its own since words never span a space.
+pub fn correct_line(words: Vec<HocrWord>, script: Script) -> Vec<HocrWord> {
+    if script.has_word_spaces() {
+        return words.into_iter().map(|word| correct_word(word, script)).collect();
+    }
+    if words.iter().any(|word| word.get_symbols().len() != 1) {
+        return words;
+    }
+    let symbols: Vec<&OcrSymbol> = words.iter().map(|word| &word.get_symbols()[0]).collect();
+    let corrections = correct_symbols(&symbols, script);
+    return words.into_iter().zip(corrections)
+        .map(|(word, correction)| match correction {
+            Some(symbol) => HocrWord::from_symbol(symbol),
+            None => word,
+        })
+        .collect();
+}
+
+/// Correct the symbols within a single word, keeping the word's bounding box.
+fn correct_word(word: HocrWord, script: Script) -> HocrWord {
+    let symbols: Vec<&OcrSymbol> = word.get_symbols().iter().collect();
+    let corrections = correct_symbols(&symbols, script);
+    if corrections.iter().all(Option::is_none) {
+        return word;
+    }
+    return word.get_symbols().iter().zip(corrections)
+        .map(|(symbol, correction)| HocrWord::from_symbol(correction.unwrap_or_else(|| symbol.clone())))
+        .reduce(|lhs, rhs| lhs + &rhs)
+        .unwrap()
+        .with_bounds(word.get_min(), word.get_max());
+}
+
+/// Run the Viterbi search over a run of symbols, returning the replacement for each symbol whose reading changed.
+fn correct_symbols(symbols: &[&OcrSymbol], script: Script) -> Vec<Option<OcrSymbol>> {
+    let candidates: Vec<Vec<(String, f32)>> = symbols.iter().map(|symbol| candidates(symbol)).collect();
+    if candidates.iter().all(|candidates| candidates.len() == 1) {
+        return vec![None; symbols.len()]; //Nothing to choose between
+    }
+
+    //best[j] is the best score for the first j symbols, and how the last segment was read
+    let mut best: Vec<Option<(f32, usize, Vec<usize>)>> = vec![None; symbols.len() + 1];
+    best[0] = Some((0.0, 0, Vec::new()));
+    for start in 0 .. symbols.len() {
+        let start_score = match &best[start] {
+            Some((score, _, _)) => *score,
+            None => continue,
+        };
+        for end in start + 1 ..= (start + MAX_WORD_LENGTH).min(symbols.len()) {
+            for (score, picks) in segment_readings(&candidates[start .. end], script) {
+                let total = start_score + score;
+                if best[end].as_ref().is_none_or(|(best_score, _, _)| total > *best_score) {
+                    best[end] = Some((total, start, picks));
+                }
+            }
+        }
+    }
+
+    let mut picks = vec![0; symbols.len()];
+    let mut end = symbols.len();
+    while end > 0 {
+        let (_, start, segment_picks) = best[end].as_ref().unwrap();
+        picks[*start .. end].copy_from_slice(segment_picks);
+        end = *start;
+    }
+    return symbols.iter().zip(picks).zip(candidates)
+        .map(|((symbol, pick), candidates)| match pick {
+            0 => None,
+            pick => {
+                let (text, confidence) = &candidates[pick];
+                Some(symbol.with_dictionary_choice(text.clone(), *confidence))
+            },
+        })
+        .collect();
+}
+
+/// The symbol's own reading first, followed by any distinct alternatives when it is uncertain.
+fn candidates(symbol: &OcrSymbol) -> Vec<(String, f32)> {
+    let mut candidates = vec![(symbol.get_text().clone(), symbol.get_confidence())];
+    if symbol.is_uncertain() {
+        for choice in symbol.get_choices() {
+            if candidates.len() >= MAX_CANDIDATES {
+                break;
+            }
+            if !candidates.iter().any(|(text, _)| text == choice.get_text()) {
+                candidates.push((choice.get_text().clone(), choice.get_confidence()));
+            }
+        }
+    }
+    return candidates;
+}
+
+/// Score every combination of candidates across a segment, returning each score with the candidate picked per symbol.
+fn segment_readings(candidates: &[Vec<(String, f32)>], script: Script) -> Vec<(f32, Vec<usize>)> {
+    let mut readings = vec![(String::new(), 0.0, Vec::with_capacity(candidates.len()))];
+    for symbol_candidates in candidates {
+        readings = readings.into_iter()
+            .flat_map(|(text, score, picks)| symbol_candidates.iter().enumerate().map(move |(index, (candidate, confidence))| {
+                let mut picks = picks.clone();
+                picks.push(index);
+                (text.clone() + candidate, score + (confidence.max(MIN_CONFIDENCE) / 100.0).ln(), picks)
+            }))
+            .collect();
+    }
+    return readings.into_iter()
+        .filter_map(|(text, score, picks)| match picks.len() {
+            1 => Some((score, picks)),
+            length if dictionary::is_word(script, &text) => Some((score + WORD_BONUS * (length - 1) as f32, picks)),
+            _ => None, //Multi-character segments only count when they're a word
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dictionary::{self, DictionaryFile, IsolatedDictionaries}, positioning_structs::{PixelPoint, SymbolChoice}};
+
+    fn symbol(text: &str, index: usize, confidence: f32, choices: &[(&str, f32)]) -> OcrSymbol {
+        let x = index as f32 * 20.0;
+        let choices = choices.iter().map(|(text, confidence)| SymbolChoice::new(text.to_string(), *confidence)).collect();
+        OcrSymbol::new(text.to_string(), PixelPoint::new(x, 0.0), PixelPoint::new(x + 20.0, 20.0), confidence, choices)
+    }
+
+    type Choices<'a> = &'a [(&'a str, f32)];
+
+    fn line(symbols: &[(&str, f32, Choices)]) -> Vec<HocrWord> {
+        symbols.iter().enumerate()
+            .map(|(index, (text, confidence, choices))| HocrWord::from_symbol(symbol(text, index, *confidence, choices)))
+            .collect()
+    }
+
+    fn text(words: &[HocrWord]) -> String {
+        words.iter().map(|word| word.get_text().as_str()).collect()
+    }
+
+    #[test]
+    fn replaces_non_word_with_dictionary_word_from_alternatives() {
+        let _dictionaries = IsolatedDictionaries::new();
+        let words = line(&[("你", 95.0, &[]), ("妤", 40.0, &[("好", 35.0), ("奸", 20.0)])]);
+        let corrected = correct_line(words, Script::Chinese);
+        assert_eq!(text(&corrected), "你好");
+        let symbol = &corrected[1].get_symbols()[0];
+        assert!(symbol.is_dictionary_corrected());
+        assert_eq!(symbol.get_raw_text(), "妤");
+        assert_eq!(symbol.get_confidence(), 35.0);
+        assert!(!corrected[0].get_symbols()[0].is_dictionary_corrected());
+    }
+
+    #[test]
+    fn keeps_confident_reading_over_unlikely_word() {
+        let _dictionaries = IsolatedDictionaries::new();
+        let words = line(&[("你", 95.0, &[]), ("妤", 80.0, &[("好", 5.0)])]);
+        assert_eq!(text(&correct_line(words, Script::Chinese)), "你妤");
+    }
+
+    #[test]
+    fn does_nothing_when_nothing_is_uncertain() {
+        let _dictionaries = IsolatedDictionaries::new();
+        let words = line(&[("你", 95.0, &[]), ("妤", 92.0, &[("好", 90.0)])]);
+        let corrected = correct_line(words, Script::Chinese);
+        assert_eq!(text(&corrected), "你妤");
+        assert!(corrected.iter().all(|word| !word.get_symbols()[0].is_dictionary_corrected()));
+    }
+
+    #[test]
+    fn skips_lines_with_multi_symbol_words() {
+        let _dictionaries = IsolatedDictionaries::new();
+        let mut words = line(&[("你", 95.0, &[]), ("妤", 40.0, &[("好", 35.0)])]);
+        let merged = words.remove(0) + &words[0];
+        let corrected = correct_line(vec![merged], Script::Chinese);
+        assert_eq!(text(&corrected), "你妤");
+        assert_eq!(corrected[0].get_symbols().len(), 2);
+    }
+
+    #[test]
+    fn corrects_korean_words_one_at_a_time() {
+        let _dictionaries = IsolatedDictionaries::new();
+        dictionary::set_language_dictionary(Script::Korean, Some(DictionaryFile::parse("사람 [saram] /person/", Script::Korean)));
+        let symbols = line(&[("사", 95.0, &[]), ("림", 50.0, &[("람", 45.0)])]);
+        let word = symbols.into_iter().reduce(|lhs, rhs| lhs + &rhs).unwrap()
+            .with_bounds(PixelPoint::new(2.0, 1.0), PixelPoint::new(38.0, 19.0));
+        let corrected = correct_line(vec![word], Script::Korean);
+        assert_eq!(text(&corrected), "사람");
+        assert_eq!(corrected[0].get_symbols().len(), 2);
+        assert!(corrected[0].get_symbols()[1].is_dictionary_corrected());
+        assert_eq!(corrected[0].get_min(), PixelPoint::new(2.0, 1.0));
+        assert_eq!(corrected[0].get_max(), PixelPoint::new(38.0, 19.0));
+    }
+}
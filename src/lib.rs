@@ -0,0 +1,21 @@
+pub mod alignment;
+pub mod batch;
+pub mod capture;
+pub mod change_detection;
+pub mod config;
+pub mod corrections;
+pub mod debug_dump;
+pub mod dictionary;
+pub mod engine;
+pub mod fonts;
+pub mod hocr;
+pub mod lattice;
+pub mod ocr;
+pub mod ocr_cache;
+pub mod screen_access;
+pub mod supported_languages;
+pub mod tesseract_api;
+pub mod positioning_structs;
+pub mod preprocessing;
+pub mod profiles;
+pub mod replay;
@@ -1,9 +1,118 @@
-mod ocr;
-mod screen_access;
-mod supported_languages;
-mod positioning_structs;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+use decky_translate::{batch, dictionary::{self, DictionaryFile}, positioning_structs::OverlayLayout, preprocessing::PreprocessingPipeline, replay, screen_access, supported_languages::{Script, SupportedLanguages}};
+
+#[derive(Parser)]
+#[command(about = "An OCR tool for learning Chinese, Japanese or Korean while playing video games")]
+struct Cli {
+    /// Read the overlay's settings from this file instead of decky_translate/config.toml in the user's config directory
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Open the overlay with the named profile from the config, rather than the one which was last active
+    #[arg(long)]
+    profile: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Open the OCR overlay window (the default when no subcommand is given)
+    Overlay,
+    /// OCR saved image files, or directories of them, and print one JSON object per image
+    Batch {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        #[command(flatten)]
+        ocr: OcrArgs,
+    },
+    /// Replay a saved capture, or a directory of frames, through the overlay's OCR and layout without opening a window,
+    /// printing one JSON object per frame
+    Replay {
+        path: PathBuf,
+        /// How the overlay lays out the recognized text
+        #[arg(long, value_enum, default_value_t = OverlayLayout::InPlace)]
+        layout: OverlayLayout,
+        #[command(flatten)]
+        ocr: OcrArgs,
+    },
+}
+
+#[derive(Args)]
+struct OcrArgs {
+    #[arg(long, value_enum, default_value_t = SupportedLanguages::ChiTra)]
+    language: SupportedLanguages,
+    /// Preprocessing steps to apply before OCR, such as "resize(3, lanczos3), invert, otsu"
+    #[arg(long)]
+    preprocessing: Option<String>,
+    /// Write every preprocessing stage and the raw hOCR for each image into a timestamped directory here
+    #[arg(long)]
+    debug_dump: Option<PathBuf>,
+    /// Extra CC-CEDICT formatted entries, such as character names, which take priority over the built in dictionary
+    #[arg(long)]
+    user_dictionary: Option<PathBuf>,
+    /// EDICT formatted dictionary for a language without one built in, such as edict2u for Japanese, or CC-Canto for
+    /// Cantonese
+    #[arg(long)]
+    dictionary: Option<PathBuf>,
+}
+
+impl OcrArgs {
+    fn pipeline(&self) -> PreprocessingPipeline {
+        match &self.preprocessing {
+            Some(steps) => PreprocessingPipeline::parse(steps).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }),
+            None => PreprocessingPipeline::default(),
+        }
+    }
+
+    fn load_dictionaries(&self) {
+        if let Some(path) = &self.user_dictionary {
+            dictionary::set_user_dictionary(Some(load_dictionary_file(path, self.language.get_script())));
+        }
+        if let Some(path) = &self.dictionary {
+            dictionary::set_language_dictionary(self.language.get_script(), Some(load_dictionary_file(path, self.language.get_script())));
+        }
+    }
+}
+
+fn load_dictionary_file(path: &Path, script: Script) -> DictionaryFile {
+    match DictionaryFile::load(path, script) {
+        Ok(dictionary) => dictionary,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            std::process::exit(1);
+        },
+    }
+}
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 1)]
 async fn main() {
-    screen_access::screen_entry().await;
-}
\ No newline at end of file
+    env_logger::init();
+    let cli = Cli::parse();
+    match cli.command.unwrap_or(Command::Overlay) {
+        Command::Overlay => {
+            if let Err(e) = screen_access::screen_entry(cli.config, cli.profile).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Batch { paths, ocr } => {
+            ocr.load_dictionaries();
+            if let Err(e) = batch::run_batch(&paths, ocr.language, &ocr.pipeline(), ocr.debug_dump.as_deref()) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Replay { path, layout, ocr } => {
+            ocr.load_dictionaries();
+            if let Err(e) = replay::run_replay(path, layout, ocr.language, &ocr.pipeline(), ocr.debug_dump.as_deref()) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
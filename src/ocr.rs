@@ -1,73 +1,490 @@
-use std::io::Cursor;
-
+use std::{path::{Path, PathBuf}, sync::{Arc, Mutex, MutexGuard, PoisonError}, time::Duration};
 
 use abort_on_drop::ChildTask;
-use image::{ImageFormat, imageops::{BiLevel, dither}};
-use screenshots::Screen;
-use tesseract::{Tesseract, PageSegMode};
-use tokio::{sync::{watch, mpsc}, task::yield_now};
+use image::{DynamicImage, GrayImage};
+use thiserror::Error;
+use tokio::{sync::{watch, mpsc}, task::yield_now, time::{Instant, MissedTickBehavior}};
+
+use crate::{alignment, dictionary, lattice, capture::{CaptureArea, CaptureError, CaptureSource}, change_detection::{FrameFingerprint, StabilityDetector, StabilitySettings, WatchSettings}, debug_dump::DebugDump, engine::{OcrEngine, TesseractEngine}, hocr, ocr_cache::OcrCache, positioning_structs::HocrWord, preprocessing::{Polarity, PreprocessingPipeline}, supported_languages::SupportedLanguages, tesseract_api::TesseractApiError};
+
+#[derive(Debug, Error)]
+pub enum OcrError {
+    #[error("Failed to load image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("Tesseract failed: {0}")]
+    Tesseract(#[from] TesseractApiError),
+}
+
+/// A single line of recognized text.
+/// Lines returned from the OCR pipeline have their words grouped into dictionary tokens.
+#[derive(Debug, Clone)]
+pub struct OcrLine {
+    words: Vec<HocrWord>,
+    is_vertical: bool,
+}
+
+impl OcrLine {
+    pub fn new(words: Vec<HocrWord>) -> Self {
+        Self {
+            words,
+            is_vertical: false,
+        }
+    }
+
+    /// Mark the line as a column read from top to bottom.
+    pub fn with_vertical(mut self, is_vertical: bool) -> Self {
+        self.is_vertical = is_vertical;
+        self
+    }
+
+    pub fn is_vertical(&self) -> bool {
+        self.is_vertical
+    }
+
+    pub fn get_words(&self) -> &Vec<HocrWord> {
+        &self.words
+    }
+
+    pub fn into_words(self) -> Vec<HocrWord> {
+        self.words
+    }
+
+    pub fn get_text(&self) -> String {
+        self.words.iter().map(|word| word.get_text().as_str()).collect()
+    }
+
+    /// The text as the engine read it, before dictionary correction.
+    pub fn get_raw_text(&self) -> String {
+        self.words.iter()
+            .flat_map(|word| word.get_symbols().iter())
+            .map(|symbol| symbol.get_raw_text().as_str())
+            .collect()
+    }
+}
+
+/// The lines recognized in a single image, along with the text polarity which was detected in it.
+#[derive(Debug, Clone)]
+pub struct OcrResult {
+    lines: Vec<OcrLine>,
+    detected_polarity: Polarity,
+    capture: Arc<DynamicImage>,
+}
+
+impl OcrResult {
+    pub fn get_lines(&self) -> &Vec<OcrLine> {
+        &self.lines
+    }
+
+    pub fn into_lines(self) -> Vec<OcrLine> {
+        self.lines
+    }
+
+    /// The polarity of the captured image, before any inversion, so light text on dark is still reported when the
+    /// OCR engine was given the image inverted by auto invert or the preprocessing steps.
+    pub fn get_detected_polarity(&self) -> Polarity {
+        self.detected_polarity
+    }
+
+    /// The image the lines were recognized in, which word bounding boxes are relative to.
+    pub fn get_capture(&self) -> &Arc<DynamicImage> {
+        &self.capture
+    }
+}
+
+/// Run the full OCR pipeline on an in-memory image, without needing a window or a screen.
+pub fn ocr_image(image: &DynamicImage, language: SupportedLanguages) -> Result<OcrResult, OcrError> {
+    return ocr_image_with_engine(image, &mut TesseractEngine::new(language), &PreprocessingPipeline::default(), None);
+}
+
+/// Run the full OCR pipeline on an in-memory image using the given engine and preprocessing.
+///
+/// When `debug_dump_dir` is given, the image at every stage and the engine's raw output are written into a new
+/// timestamped directory inside it.
+pub fn ocr_image_with_engine<E: OcrEngine>(image: &DynamicImage, engine: &mut E, pipeline: &PreprocessingPipeline, debug_dump_dir: Option<&Path>) -> Result<OcrResult, OcrError> {
+    return ocr_image_with_optional_cache(image, engine, pipeline, debug_dump_dir, None);
+}
+
+/// Like `ocr_image_with_engine`, but reuses the engine's output from the cache when the same preprocessed image
+/// has been seen before.
+pub fn ocr_image_with_cache<E: OcrEngine>(image: &DynamicImage, engine: &mut E, pipeline: &PreprocessingPipeline, debug_dump_dir: Option<&Path>, cache: &mut OcrCache) -> Result<OcrResult, OcrError> {
+    return ocr_image_with_optional_cache(image, engine, pipeline, debug_dump_dir, Some(cache));
+}
+
+fn ocr_image_with_optional_cache<E: OcrEngine>(image: &DynamicImage, engine: &mut E, pipeline: &PreprocessingPipeline, debug_dump_dir: Option<&Path>, cache: Option<&mut OcrCache>) -> Result<OcrResult, OcrError> {
+    let mut debug_dump = debug_dump_dir.and_then(|directory| DebugDump::create(directory)
+        .map_err(|e| log::error!("Failed to create debug directory in {}: {}", directory.display(), e))
+        .ok());
+    let (processed_image, detected_polarity) = match &mut debug_dump {
+        Some(debug_dump) => {
+            debug_dump.save_image("capture", image);
+            debug_dump.save_text("pipeline.txt", &pipeline.to_string());
+            pipeline.apply_with_stages(image, |name, stage_image| debug_dump.save_image(name, stage_image))
+        },
+        None => pipeline.apply(image),
+    };
+    if let Some(debug_dump) = &mut debug_dump {
+        debug_dump.save_image("final", &DynamicImage::ImageLuma8(processed_image.clone()));
+    }
+    let lines = match cache {
+        Some(cache) => {
+            let key = OcrCache::key(&processed_image, &engine.get_settings(), &format!("{} auto_invert={}", pipeline, pipeline.get_auto_invert()));
+            match cache.get(key) {
+                Some(lines) => Ok(lines),
+                None => {
+                    let lines = recognize(&processed_image, engine, debug_dump.as_ref());
+                    if let Ok(lines) = &lines {
+                        cache.insert(key, lines.clone());
+                    }
+                    lines
+                },
+            }
+        },
+        None => recognize(&processed_image, engine, debug_dump.as_ref()),
+    };
+    if let Some(debug_dump) = &debug_dump {
+        log::info!("Wrote OCR debug dump to {}", debug_dump.get_directory().display());
+    }
+    let lines = lines?;
+    return Ok(OcrResult {
+        lines: tokenize_lines(alignment::align_to_ink(lines, &processed_image), image, &processed_image, engine.get_language()),
+        detected_polarity,
+        capture: Arc::new(image.clone()),
+    });
+}
+
+fn recognize<E: OcrEngine>(processed_image: &GrayImage, engine: &mut E, debug_dump: Option<&DebugDump>) -> Result<Vec<OcrLine>, OcrError> {
+    engine.set_keep_raw_output(debug_dump.is_some());
+    let lines = engine.recognize(processed_image);
+    if let Some(debug_dump) = debug_dump {
+        if let Some(raw_output) = engine.get_last_raw_output() {
+            debug_dump.save_text("output.hocr", raw_output);
+            match hocr::parse(raw_output) {
+                Ok(document) => debug_dump.save_text("layout.txt", &document.to_string()),
+                Err(e) => log::error!("Failed to parse hOCR output: {}", e),
+            }
+        }
+    }
+    return lines;
+}
+
+/// Run the full OCR pipeline on an image file, such as a saved PNG screenshot.
+pub fn ocr_file<P: AsRef<Path>>(path: P, language: SupportedLanguages) -> Result<OcrResult, OcrError> {
+    let image = image::open(path)?;
+    return ocr_image(&image, language);
+}
+
+/// Sent from the OCR worker back to the overlay.
+#[derive(Debug)]
+pub enum WorkerMessage {
+    Result(OcrResult),
+    /// Watch mode saw the capture area change, so the overlay should schedule a new scan.
+    TextChanged,
+}
 
-use crate::supported_languages::SupportedLanguages;
+/// Settings which the OCR worker applies to every scan.
+#[derive(Debug, Clone)]
+pub struct OcrWorkerSettings {
+    pipeline: PreprocessingPipeline,
+    debug_dump_dir: Option<PathBuf>,
+    watch: Option<WatchSettings>,
+    stability: Option<StabilitySettings>,
+    cache_capacity: usize,
+}
+
+impl OcrWorkerSettings {
+    pub fn new(pipeline: PreprocessingPipeline) -> Self {
+        Self {
+            pipeline,
+            debug_dump_dir: None,
+            watch: None,
+            stability: None,
+            cache_capacity: 0,
+        }
+    }
+
+    pub fn with_debug_dump_dir(mut self, debug_dump_dir: Option<PathBuf>) -> Self {
+        self.debug_dump_dir = debug_dump_dir;
+        self
+    }
+
+    pub fn with_watch(mut self, watch: Option<WatchSettings>) -> Self {
+        self.watch = watch;
+        self
+    }
+
+    pub fn with_stability(mut self, stability: Option<StabilitySettings>) -> Self {
+        self.stability = stability;
+        self
+    }
+
+    /// Remember the results of this many recent scans, or none when the capacity is 0.
+    pub fn with_cache_capacity(mut self, cache_capacity: usize) -> Self {
+        self.cache_capacity = cache_capacity;
+        self
+    }
+}
 
 #[tokio::main]
-pub async fn build_ocr_worker(mut receiver: watch::Receiver<(i32, i32, u32, u32)>, sender: mpsc::Sender<String>, language: SupportedLanguages) {
-    let mut window_position: Option<(i32, i32, u32, u32)> = None;
+pub async fn build_ocr_worker<E, C>(mut receiver: watch::Receiver<CaptureArea>, sender: mpsc::Sender<WorkerMessage>, mut engine: E, capture_source: C, settings: OcrWorkerSettings)
+where
+    E: OcrEngine + Send + 'static,
+    C: CaptureSource + Send + 'static,
+{
+    if let Err(e) = engine.initialize() {
+        log::error!("Failed to initialize OCR engine: {}", e);
+    }
+    let engine = Arc::new(Mutex::new(engine)); //Shared with each job so the engine stays warm between scans
+    let capture_source = Arc::new(Mutex::new(capture_source));
+    let cache = match settings.cache_capacity {
+        0 => None,
+        capacity => Some(Arc::new(Mutex::new(OcrCache::new(capacity)))),
+    };
+    let mut window_position: Option<CaptureArea> = None;
+    let mut watched_area: Option<CaptureArea> = None;
+    let mut baseline: Option<FrameFingerprint> = None; //What the area looked like after the last scan was displayed
+    let mut watch_interval = tokio::time::interval(settings.watch.map(|watch| watch.get_interval()).unwrap_or(Duration::from_secs(1)));
+    watch_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
     loop {
+        let job = execute_ocr(window_position, engine.clone(), capture_source.clone(), cache.clone(), settings.clone());
         tokio::select! {
             biased;
-            _ = receiver.changed() => {
+            changed = receiver.changed() => {
+                if changed.is_err() {
+                    return; //The overlay has closed, or restarted the worker with a new profile
+                }
                 window_position = Some(*receiver.borrow());
+                watched_area = window_position;
+                baseline = None;
             }
-            Ok(Some(parsed_text)) = ChildTask::from(tokio::spawn(execute_ocr(window_position, language))) => {
-                sender.send(parsed_text).await.unwrap();
+            result = async { ChildTask::from(tokio::spawn(job)).await }, if window_position.is_some() => {
+                match result {
+                    Ok(Some(result)) => {
+                        if sender.send(WorkerMessage::Result(result)).await.is_err() {
+                            return;
+                        }
+                    },
+                    Ok(None) => {},
+                    Err(e) => log::error!("OCR job failed: {}", e), //Dropped rather than retried, in case it would fail again
+                }
                 window_position = None;
+                baseline = None;
+            }
+            _ = watch_interval.tick(), if settings.watch.is_some() && watched_area.is_some() && window_position.is_none() => {
+                let capture = lock(&capture_source).capture(watched_area.unwrap());
+                match capture {
+                    Ok(image) => {
+                        let fingerprint = FrameFingerprint::from_image(&image);
+                        match &baseline {
+                            Some(previous) if previous.difference(&fingerprint) > settings.watch.unwrap().get_threshold() => {
+                                baseline = None;
+                                if sender.send(WorkerMessage::TextChanged).await.is_err() {
+                                    return;
+                                }
+                            },
+                            Some(_) => {},
+                            None => baseline = Some(fingerprint),
+                        }
+                    },
+                    Err(e) => log::warn!("Watch capture failed: {}", e),
+                }
             }
         }
     }
 }
 
 
-async fn execute_ocr(t: Option<(i32, i32, u32, u32)>, language: SupportedLanguages) -> Option<String> {
+async fn execute_ocr<E: OcrEngine, C: CaptureSource>(t: Option<CaptureArea>, engine: Arc<Mutex<E>>, capture_source: Arc<Mutex<C>>, cache: Option<Arc<Mutex<OcrCache>>>, settings: OcrWorkerSettings) -> Option<OcrResult> {
     match t {
-        Some((x, y, width, height)) => {
-            let screen = Screen::from_point(x, y).unwrap();
-            let display_position = screen.display_info;
-            let image = screen.capture_area(x - display_position.x, y - display_position.y, width, height).unwrap();
-            let buffer = image.buffer();
+        Some(area) => {
+            let is_live = lock(&capture_source).is_live();
+            let capture = match settings.stability {
+                Some(stability) if is_live => capture_settled(area, &capture_source, stability).await,
+                _ => lock(&capture_source).capture(area),
+            };
+            let image = match capture {
+                Ok(image) => image,
+                Err(e) => {
+                    log::error!("Capture failed: {}", e);
+                    return None;
+                }
+            };
+
             yield_now().await;
 
-            let image = image::load_from_memory(buffer).unwrap();
-                        
-            let image_width = image.width();
-            let image_height = image.height();
+            let result = {
+                let mut engine = lock(&engine);
+                let result = match &cache {
+                    Some(cache) => {
+                        let mut cache = lock(cache);
+                        let result = ocr_image_with_cache(&image, &mut *engine, &settings.pipeline, settings.debug_dump_dir.as_deref(), &mut cache);
+                        log::info!("OCR cache: {} ({} stored)", cache.get_stats(), cache.len());
+                        result
+                    },
+                    None => ocr_image_with_engine(&image, &mut *engine, &settings.pipeline, settings.debug_dump_dir.as_deref()),
+                };
+                if let Some(metrics) = engine.get_metrics() {
+                    log::info!("OCR engine metrics: {}", metrics);
+                }
+                result
+            };
+            match result {
+                Ok(result) => Some(result),
+                Err(e) => {
+                    log::error!("OCR failed: {}", e);
+                    None
+                }
+            }
+        },
+        None => None,
+    }
 
-            yield_now().await;
+}
 
-            let image = image.resize(image_width * 4, image_height * 4, image::imageops::FilterType::CatmullRom);
-            yield_now().await;
-            let image = image.blur(0.9);
-            yield_now().await;
-            let color_map = BiLevel;
-            let mut image: image::ImageBuffer<image::Luma<u8>, Vec<u8>> = image.to_luma8();
-            dither(&mut image, &color_map);
+/// Lock state which is shared with the OCR jobs.
+///
+/// A job which panicked, such as inside Tesseract, leaves the mutex poisoned. The engine, capture source and cache are
+/// still usable afterwards, since the Tesseract engine starts a new instance if its old one was lost mid-scan, so later
+/// scans carry on rather than every one of them panicking too.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    return mutex.lock().unwrap_or_else(PoisonError::into_inner);
+}
 
-            yield_now().await;
+/// Keep capturing the area until text which is being typed out has finished, returning the settled capture.
+/// This is only used with live sources, since others would step through their frames or return the same image.
+async fn capture_settled<C: CaptureSource>(area: CaptureArea, capture_source: &Arc<Mutex<C>>, stability: StabilitySettings) -> Result<DynamicImage, CaptureError> {
+    let mut detector = StabilityDetector::new(stability);
+    let deadline = Instant::now() + stability.get_max_wait();
+    loop {
+        let image = lock(capture_source).capture(area)?;
+        if detector.push(&image) {
+            return Ok(image);
+        }
+        if Instant::now() >= deadline {
+            log::info!("Capture area was still changing after {:?}, scanning anyway", stability.get_max_wait());
+            return Ok(image);
+        }
+        tokio::time::sleep(stability.get_interval()).await;
+    }
+}
 
-            let mut tesseract = Tesseract::new(None, Some(&language.to_string())).unwrap();
-            tesseract.set_page_seg_mode(PageSegMode::PsmSingleBlock);
+/// Map engine output back onto the captured image's coordinates, correct it against the dictionary and group each
+/// line's words into dictionary tokens.
+fn tokenize_lines(lines: Vec<OcrLine>, image: &DynamicImage, processed_image: &GrayImage, language: SupportedLanguages) -> Vec<OcrLine> {
+    //OCR image may have been resized during preprocessing
+    let x_scale = image.width() as f32 / processed_image.width() as f32;
+    let y_scale = image.height() as f32 / processed_image.height() as f32;
+    let mut tokenized_lines = Vec::with_capacity(lines.len());
+    for line in lines {
+        let is_vertical = line.is_vertical();
+        let words = line.into_words().iter().map(|word| word.scaled(x_scale, y_scale)).collect();
+        let tokenized_line = OcrLine::new(group_into_tokens(lattice::correct_line(words, language.get_script()), language))
+            .with_vertical(is_vertical);
+        if tokenized_line.get_words().is_empty() {
+            continue;
+        }
+        if tokenized_line.get_text() != tokenized_line.get_raw_text() {
+            log::info!("Dictionary correction read {} as {}", tokenized_line.get_raw_text(), tokenized_line.get_text());
+        }
+        tokenized_lines.push(tokenized_line);
+    }
+    return tokenized_lines;
+}
 
-            let mut bytes: Vec<u8> = Vec::with_capacity(image.len());
-            image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).unwrap();
+/// Merge each line's symbols into one word per dictionary token, keeping symbols the tokenizer skipped as they are.
+/// Languages which put spaces between words keep the engine's words instead.
+pub fn group_into_tokens(words: Vec<HocrWord>, language: SupportedLanguages) -> Vec<HocrWord> {
+    let script = language.get_script();
+    if script.has_word_spaces() {
+        return words;
+    }
+    let raw_text: String = words.iter().map(|hocr_word| hocr_word.get_text().as_str()).collect();
+    let tokenized_text = dictionary::tokenize(script, &raw_text);
+    let mut tokenized_words = Vec::with_capacity(tokenized_text.len());
+    let mut i = 0;
+    for token in tokenized_text {
+        if let Some(index) = (i .. words.len()).find(|index| token.starts_with(words[*index].get_text().as_str())) {
+            tokenized_words.extend(words[i .. index].iter().cloned());
+            let mut end = index + 1;
+            let mut length = words[index].get_text().len();
+            while length < token.len() && end < words.len() {
+                length += words[end].get_text().len();
+                end += 1;
+            }
+            tokenized_words.push(words[index+1 .. end].iter().fold(words[index].clone(), |lhs, rhs| lhs + rhs));
+            i = end;
+        }
+    }
+    tokenized_words.extend(words[i ..].iter().cloned());
+    return tokenized_words;
+}
 
-            let mut tesseract = tesseract.set_image_from_mem(&bytes).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, thread};
+    use crate::capture::ReplaySource;
 
-            yield_now().await;
+    /// An engine which never finds any text.
+    struct BlankEngine;
 
-            let hocr_text = tesseract.get_hocr_text(0).unwrap();
-            return Some(hocr_text);
+    impl OcrEngine for BlankEngine {
+        fn recognize(&mut self, _image: &GrayImage) -> Result<Vec<OcrLine>, OcrError> {
+            Ok(Vec::new())
+        }
 
-        },
-        None => None,
+        fn get_language(&self) -> SupportedLanguages {
+            SupportedLanguages::ChiSim
+        }
+    }
+
+    /// A capture source which returns a white image the size of the area.
+    struct BlankSource;
+
+    impl CaptureSource for BlankSource {
+        fn capture(&mut self, area: CaptureArea) -> Result<DynamicImage, CaptureError> {
+            Ok(DynamicImage::ImageLuma8(GrayImage::from_pixel(area.get_width(), area.get_height(), image::Luma([255]))))
+        }
     }
 
-}
\ No newline at end of file
+    fn poison<T: Send + 'static>(mutex: &Arc<Mutex<T>>) {
+        let mutex = mutex.clone();
+        let _ = thread::spawn(move || {
+            let _guard = mutex.lock().unwrap();
+            panic!("Poisoning the mutex for the test");
+        }).join();
+    }
+
+    #[tokio::test]
+    async fn scans_after_a_job_panicked() {
+        let engine = Arc::new(Mutex::new(BlankEngine));
+        let capture_source = Arc::new(Mutex::new(BlankSource));
+        let cache = Arc::new(Mutex::new(OcrCache::new(4)));
+        poison(&engine);
+        poison(&capture_source);
+        poison(&cache);
+        assert!(engine.is_poisoned() && capture_source.is_poisoned() && cache.is_poisoned());
+        let settings = OcrWorkerSettings::new(PreprocessingPipeline::default()).with_cache_capacity(4);
+        let result = execute_ocr(Some(CaptureArea::new(0, 0, 32, 16)), engine, capture_source, Some(cache.clone()), settings).await;
+        assert!(result.unwrap().get_lines().is_empty());
+        assert_eq!(lock(&cache).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn replays_frames_without_waiting_for_them_to_settle() {
+        let directory = std::env::temp_dir().join(format!("decky_translate_settle_replay_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        GrayImage::from_pixel(8, 8, image::Luma([0])).save(directory.join("0.png")).unwrap();
+        GrayImage::from_pixel(8, 8, image::Luma([255])).save(directory.join("1.png")).unwrap();
+        let capture_source = Arc::new(Mutex::new(ReplaySource::new(directory.clone()).unwrap()));
+        assert!(!lock(&capture_source).is_live());
+        let stability = StabilitySettings::new(3, Duration::from_millis(100), 0.0, Duration::from_secs(10));
+        let settings = OcrWorkerSettings::new(PreprocessingPipeline::default()).with_stability(Some(stability));
+        let start = Instant::now();
+        let result = execute_ocr(Some(CaptureArea::new(0, 0, 8, 8)), Arc::new(Mutex::new(BlankEngine)), capture_source.clone(), None, settings).await;
+        let next_frame = lock(&capture_source).capture(CaptureArea::default()).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(start.elapsed() < stability.get_max_wait() / 2);
+        assert_eq!(result.unwrap().get_capture().to_luma8().get_pixel(0, 0).0, [0]);
+        assert_eq!(next_frame.to_luma8().get_pixel(0, 0).0, [255]); //Only the first frame was used
+    }
+}
@@ -0,0 +1,186 @@
+use std::{collections::{HashMap, VecDeque, hash_map::DefaultHasher}, fmt, hash::{Hash, Hasher}};
+
+use image::GrayImage;
+
+use crate::ocr::OcrLine;
+
+/// How often the cache has saved a trip to the engine.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    hits: u64,
+    misses: u64,
+}
+
+impl CacheStats {
+    pub fn get_hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn get_misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lookups = self.hits + self.misses;
+        let hit_rate = match lookups {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64 * 100.0,
+        };
+        write!(f, "{} hit(s), {} miss(es) ({:.1}% hit rate)", self.hits, self.misses, hit_rate)
+    }
+}
+
+/// Remembers the engine's output for recently seen preprocessed images, so rescanning the same dialogue box
+/// doesn't run the engine again.
+///
+/// Results are keyed by a hash of the preprocessed image along with the engine and preprocessing settings,
+/// and the least recently used result is dropped once `capacity` results are stored.
+#[derive(Debug)]
+pub struct OcrCache {
+    capacity: usize,
+    entries: HashMap<u64, Vec<OcrLine>>,
+    recently_used: VecDeque<u64>,
+    stats: CacheStats,
+}
+
+impl OcrCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            recently_used: VecDeque::with_capacity(capacity),
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn get_stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn key(image: &GrayImage, engine_settings: &str, pipeline_settings: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        image.dimensions().hash(&mut hasher);
+        image.as_raw().hash(&mut hasher);
+        engine_settings.hash(&mut hasher);
+        pipeline_settings.hash(&mut hasher);
+        return hasher.finish();
+    }
+
+    pub fn get(&mut self, key: u64) -> Option<Vec<OcrLine>> {
+        match self.entries.get(&key) {
+            Some(lines) => {
+                self.stats.hits += 1;
+                let lines = lines.clone();
+                self.touch(key);
+                Some(lines)
+            },
+            None => {
+                self.stats.misses += 1;
+                None
+            },
+        }
+    }
+
+    pub fn insert(&mut self, key: u64, lines: Vec<OcrLine>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(key, lines).is_some() {
+            self.touch(key);
+            return;
+        }
+        self.recently_used.push_back(key);
+        while self.entries.len() > self.capacity {
+            if let Some(oldest) = self.recently_used.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn touch(&mut self, key: u64) {
+        self.recently_used.retain(|used| *used != key);
+        self.recently_used.push_back(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    use crate::positioning_structs::{HocrWord, PixelPoint};
+
+    fn lines(text: &str) -> Vec<OcrLine> {
+        let word = HocrWord::new(text.to_string(), PixelPoint::new(0.0, 0.0), PixelPoint::new(10.0, 10.0), 95.0);
+        return vec![OcrLine::new(vec![word])];
+    }
+
+    fn text(lines: Option<Vec<OcrLine>>) -> Option<String> {
+        lines.map(|lines| lines.iter().map(|line| line.get_text()).collect())
+    }
+
+    #[test]
+    fn key_depends_on_image_and_settings() {
+        let image = GrayImage::from_pixel(8, 4, Luma([200]));
+        let key = OcrCache::key(&image, "chi_sim", "threshold");
+        assert_eq!(key, OcrCache::key(&image.clone(), "chi_sim", "threshold"));
+        assert_ne!(key, OcrCache::key(&GrayImage::from_pixel(8, 4, Luma([0])), "chi_sim", "threshold"));
+        assert_ne!(key, OcrCache::key(&GrayImage::from_pixel(4, 8, Luma([200])), "chi_sim", "threshold"));
+        assert_ne!(key, OcrCache::key(&image, "chi_tra", "threshold"));
+        assert_ne!(key, OcrCache::key(&image, "chi_sim", "invert"));
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let mut cache = OcrCache::new(2);
+        assert_eq!(text(cache.get(1)), None);
+        cache.insert(1, lines("你好"));
+        assert_eq!(text(cache.get(1)), Some("你好".to_string()));
+        assert_eq!(cache.get_stats().get_hits(), 1);
+        assert_eq!(cache.get_stats().get_misses(), 1);
+        assert_eq!(cache.get_stats().to_string(), "1 hit(s), 1 miss(es) (50.0% hit rate)");
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = OcrCache::new(2);
+        cache.insert(1, lines("一"));
+        cache.insert(2, lines("二"));
+        cache.get(1);
+        cache.insert(3, lines("三"));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(text(cache.get(2)), None);
+        assert_eq!(text(cache.get(1)), Some("一".to_string()));
+        assert_eq!(text(cache.get(3)), Some("三".to_string()));
+    }
+
+    #[test]
+    fn reinserting_replaces_and_refreshes() {
+        let mut cache = OcrCache::new(2);
+        cache.insert(1, lines("一"));
+        cache.insert(2, lines("二"));
+        cache.insert(1, lines("壹"));
+        cache.insert(3, lines("三"));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(text(cache.get(1)), Some("壹".to_string()));
+        assert_eq!(text(cache.get(2)), None);
+    }
+
+    #[test]
+    fn zero_capacity_stores_nothing() {
+        let mut cache = OcrCache::new(0);
+        cache.insert(1, lines("一"));
+        assert!(cache.is_empty());
+        assert_eq!(text(cache.get(1)), None);
+    }
+}
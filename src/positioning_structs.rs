@@ -1,12 +1,25 @@
-use std::{ops::{Sub, Add}, cmp::{min, max}};
+use std::ops::{Sub, Add};
 
-use chinese_dictionary::query_by_chinese;
-use wgpu_glyph::{FontId, ab_glyph::{self, Rect, PxScale}, OwnedSection, Section, OwnedText, GlyphBrush, GlyphCruncher};
+use clap::ValueEnum;
+use serde::{Serialize, Deserialize};
+use wgpu_glyph::{FontId, ab_glyph::{self, Rect, PxScale}, OwnedSection, Section, OwnedText, GlyphCruncher};
 use winit::dpi::{PhysicalPosition, Size, PhysicalSize};
 
-use crate::{screen_access::Vertex, supported_languages::SupportedLanguages};
-
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+use crate::{dictionary, ocr, screen_access::Vertex, supported_languages::SupportedLanguages};
+
+/// Fraction of the em square covered by the ink of a typical CJK glyph, used to size text drawn in place.
+const GLYPH_INK_HEIGHT: f32 = 0.9;
+/// Words and symbols with a confidence below this are drawn in red.
+const CONFIDENCE_THRESHOLD: f32 = 90.0;
+/// Alternatives beyond this can't be picked with the number keys.
+const MAX_CHOICE_KEYS: usize = 9;
+/// Pixels of background drawn around each word in the in-place layout.
+const IN_PLACE_PADDING: f32 = 2.0;
+/// Width in pixels of the lines drawn in the outline layout.
+const OUTLINE_THICKNESS: f32 = 2.0;
+const BACKGROUND_COLOUR: [f32; 3] = [1.0, 1.0, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct PixelPoint {
     x: f32,
     y: f32,
@@ -41,9 +54,9 @@ impl From<(f32, f32)> for PixelPoint {
     }
 }
 
-impl Into<(f32, f32)> for PixelPoint {
-    fn into(self) -> (f32, f32) {
-        (self.x, self.y)
+impl From<PixelPoint> for (f32, f32) {
+    fn from(point: PixelPoint) -> Self {
+        (point.x, point.y)
     }
 }
 
@@ -76,6 +89,17 @@ impl Sub<PixelPoint> for PixelPoint {
     }
 }
 
+impl Add<PixelPoint> for PixelPoint {
+    type Output = PixelPoint;
+
+    fn add(self, rhs: PixelPoint) -> Self {
+        Self {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y
+        }
+    }
+}
+
 impl Ord for PixelPoint {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         let combined_self = self.x + self.y;
@@ -84,6 +108,12 @@ impl Ord for PixelPoint {
     }
 }
 
+impl PartialOrd for PixelPoint {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
 impl Eq for PixelPoint {}
 
 pub struct PixelArea {
@@ -91,41 +121,177 @@ pub struct PixelArea {
     max: PixelPoint,
 }
 
-impl Into<Size> for PixelArea {
-    fn into(self) -> Size {
-        let difference = self.max - self.min;
+impl From<PixelArea> for Size {
+    fn from(area: PixelArea) -> Self {
+        let difference = area.max - area.min;
         PhysicalSize::new(difference.x, difference.y).into()
     }
 }
 
+/// An alternative reading which the engine considered for a symbol, with its confidence from 0 to 100.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolChoice {
+    text: String,
+    confidence: f32,
+}
+
+impl SymbolChoice {
+    pub fn new(text: String, confidence: f32) -> Self {
+        Self {
+            text,
+            confidence,
+        }
+    }
+
+    pub fn get_text(&self) -> &String {
+        &self.text
+    }
+
+    pub fn get_confidence(&self) -> f32 {
+        self.confidence
+    }
+}
+
+/// A single recognized character, with the alternatives the engine considered for it.
+#[derive(Debug, Clone)]
+pub struct OcrSymbol {
+    text: String,
+    min: PixelPoint,
+    max: PixelPoint,
+    confidence: f32,
+    choices: Vec<SymbolChoice>,
+    raw_text: Option<String>, //What the engine read, when dictionary correction picked something else
+}
+
+impl OcrSymbol {
+    pub fn new(text: String, min: PixelPoint, max: PixelPoint, confidence: f32, choices: Vec<SymbolChoice>) -> Self {
+        Self {
+            text,
+            min,
+            max,
+            confidence,
+            choices,
+            raw_text: None,
+        }
+    }
+
+    pub fn get_text(&self) -> &String {
+        &self.text
+    }
+
+    pub fn get_min(&self) -> PixelPoint {
+        self.min
+    }
+
+    pub fn get_max(&self) -> PixelPoint {
+        self.max
+    }
+
+    pub fn get_confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    /// Alternative readings, best first, which may include the chosen text itself.
+    pub fn get_choices(&self) -> &Vec<SymbolChoice> {
+        &self.choices
+    }
+
+    /// The text the engine originally read, before any dictionary correction.
+    pub fn get_raw_text(&self) -> &String {
+        self.raw_text.as_ref().unwrap_or(&self.text)
+    }
+
+    /// Whether dictionary correction replaced the engine's reading with one of its alternatives.
+    pub fn is_dictionary_corrected(&self) -> bool {
+        self.raw_text.is_some()
+    }
+
+    /// The symbol read as one of its alternatives instead, because that forms a dictionary word.
+    pub fn with_dictionary_choice(&self, text: String, confidence: f32) -> Self {
+        Self {
+            text,
+            min: self.min,
+            max: self.max,
+            confidence,
+            choices: self.choices.clone(),
+            raw_text: Some(self.get_raw_text().clone()),
+        }
+    }
+
+    /// Whether the engine was unsure of this symbol, in which case it is drawn in red.
+    pub fn is_uncertain(&self) -> bool {
+        self.confidence < CONFIDENCE_THRESHOLD
+    }
+
+    /// The symbol with its text replaced by one the user has picked, which is then treated as certain.
+    pub fn corrected(&self, text: String) -> Self {
+        Self {
+            text,
+            min: self.min,
+            max: self.max,
+            confidence: 100.0,
+            choices: self.choices.clone(),
+            raw_text: None,
+        }
+    }
+
+    fn scaled(&self, x_factor: f32, y_factor: f32) -> Self {
+        Self {
+            text: self.text.clone(),
+            min: PixelPoint::new(self.min.x * x_factor, self.min.y * y_factor),
+            max: PixelPoint::new(self.max.x * x_factor, self.max.y * y_factor),
+            confidence: self.confidence,
+            choices: self.choices.clone(),
+            raw_text: self.raw_text.clone(),
+        }
+    }
+}
+
+/// A run of recognized text with its bounding box, made up of one or more symbols.
 #[derive(Debug, Clone)]
 pub struct HocrWord {
     text: String,
     min: PixelPoint,
     max: PixelPoint,
     confidence: f32,
+    symbols: Vec<OcrSymbol>,
 }
 
 impl Add<&HocrWord> for HocrWord {
     type Output = HocrWord;
 
-    fn add(self, rhs: &HocrWord) -> Self::Output {
+    fn add(mut self, rhs: &HocrWord) -> Self::Output {
+        self.symbols.extend(rhs.symbols.iter().cloned());
         HocrWord {
             text: self.text + &rhs.text,
-            min: min(self.min, rhs.min),
-            max: max(self.max, rhs.max),
+            min: PixelPoint::new(self.min.x.min(rhs.min.x), self.min.y.min(rhs.min.y)),
+            max: PixelPoint::new(self.max.x.max(rhs.max.x), self.max.y.max(rhs.max.y)),
             confidence: self.confidence.min(rhs.confidence),
+            symbols: self.symbols,
         }
     }
 }
 
 impl HocrWord {
+    /// A word without any per-symbol detail, which is treated as a single symbol with no alternatives.
     pub fn new(text: String, min: PixelPoint, max: PixelPoint, confidence: f32) -> Self {
+        let symbol = OcrSymbol::new(text.clone(), min, max, confidence, Vec::new());
         Self { 
             text,
             min,
             max,
             confidence,
+            symbols: vec![symbol],
+        }
+    }
+
+    pub fn from_symbol(symbol: OcrSymbol) -> Self {
+        Self {
+            text: symbol.text.clone(),
+            min: symbol.min,
+            max: symbol.max,
+            confidence: symbol.confidence,
+            symbols: vec![symbol],
         }
     }
 
@@ -137,38 +303,146 @@ impl HocrWord {
         self.min
     }
 
+    pub fn get_max(&self) -> PixelPoint {
+        self.max
+    }
+
+    pub fn get_confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    pub fn get_symbols(&self) -> &Vec<OcrSymbol> {
+        &self.symbols
+    }
+
+    /// Move the word to a new bounding box, along with its symbol when it only has one.
+    pub fn with_bounds(mut self, min: PixelPoint, max: PixelPoint) -> Self {
+        self.min = min;
+        self.max = max;
+        if let [symbol] = self.symbols.as_mut_slice() {
+            symbol.min = min;
+            symbol.max = max;
+        }
+        self
+    }
+
+    pub fn scaled(&self, x_factor: f32, y_factor: f32) -> Self {
+        Self {
+            text: self.text.clone(),
+            min: PixelPoint::new(self.min.x * x_factor, self.min.y * y_factor),
+            max: PixelPoint::new(self.max.x * x_factor, self.max.y * y_factor),
+            confidence: self.confidence,
+            symbols: self.symbols.iter().map(|symbol| symbol.scaled(x_factor, y_factor)).collect(),
+        }
+    }
+
     fn get_scale(&self) -> f32 {
         self.max.y - self.min.y
     }
+
+    /// The size of the word across its line, which in a column is its width rather than its height.
+    fn get_scale_in(&self, is_vertical: bool) -> f32 {
+        match is_vertical {
+            true => self.max.x - self.min.x,
+            false => self.get_scale(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct PresentableWord {
     text: String,
     min: PixelPoint,
+    max: PixelPoint,
+    text_position: PixelPoint,
+    scale: PxScale,
     confidence: f32,
+    symbols: Vec<OcrSymbol>,
     is_highlighted: bool,
+    is_vertical: bool,
 }
 
 impl PresentableWord {
-    pub fn new(text: String, min: PixelPoint, confidence: f32) -> Self {
+    pub fn new(text: String, min: PixelPoint, max: PixelPoint, scale: PxScale, confidence: f32, symbols: Vec<OcrSymbol>) -> Self {
         Self { 
             text,
             min,
+            max,
+            text_position: min,
+            scale,
             confidence,
-            is_highlighted: false
+            symbols,
+            is_highlighted: false,
+            is_vertical: false,
         }
     }
 
+    /// Draw the word's characters stacked from top to bottom, for text written in columns.
+    pub fn with_vertical(mut self, is_vertical: bool) -> Self {
+        self.is_vertical = is_vertical;
+        self
+    }
+
+    pub fn is_vertical(&self) -> bool {
+        self.is_vertical
+    }
+
+    pub fn get_text(&self) -> &String {
+        &self.text
+    }
+
     pub fn get_min(&self) -> PixelPoint {
         self.min
     }
 
-    pub fn is_within_bounds(&self, position: &PixelPoint, scale: PxScale) -> bool {
-        let cursor_x: f32 = position.x as f32;
-        let cursor_y: f32 = position.y as f32;
-        return cursor_x > self.min.x && cursor_x <= self.min.x + (scale.x * self.text.chars().count() as f32)
-            && cursor_y > self.min.y && cursor_y <= self.min.y + scale.y as f32;
+    pub fn get_max(&self) -> PixelPoint {
+        self.max
+    }
+
+    pub fn get_confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    /// The recognized symbols making up the word, with their boxes where they were found in the capture.
+    pub fn get_symbols(&self) -> &Vec<OcrSymbol> {
+        &self.symbols
+    }
+
+    /// Whether any of the word's symbols were changed by dictionary correction.
+    pub fn is_dictionary_corrected(&self) -> bool {
+        self.symbols.iter().any(|symbol| symbol.is_dictionary_corrected())
+    }
+
+    /// Which of the word's symbols is under the given position, hit-testing against each symbol's own box.
+    ///
+    /// Symbols keep the boxes they were found at in the capture, which is only where the word is drawn in place,
+    /// so the position is first mapped from the drawn word onto the area its symbols cover.
+    /// Between symbols, the nearest one along the line is picked.
+    pub fn symbol_at(&self, position: &PixelPoint) -> Option<usize> {
+        if !self.is_within_bounds(position) || self.symbols.is_empty() {
+            return None;
+        }
+        let symbols_min = self.symbols.iter().map(|symbol| symbol.min).reduce(|lhs, rhs| PixelPoint::new(lhs.x.min(rhs.x), lhs.y.min(rhs.y))).unwrap();
+        let symbols_max = self.symbols.iter().map(|symbol| symbol.max).reduce(|lhs, rhs| PixelPoint::new(lhs.x.max(rhs.x), lhs.y.max(rhs.y))).unwrap();
+        let map = |value: f32, min: f32, max: f32, target_min: f32, target_max: f32| match max > min {
+            true => target_min + (value - min) / (max - min) * (target_max - target_min),
+            false => target_min,
+        };
+        let position = PixelPoint::new(
+            map(position.x, self.min.x, self.max.x, symbols_min.x, symbols_max.x),
+            map(position.y, self.min.y, self.max.y, symbols_min.y, symbols_max.y),
+        );
+        let along = |point: PixelPoint| if self.is_vertical { point.y } else { point.x };
+        let distance = |symbol: &OcrSymbol| (along(symbol.min) - along(position)).max(along(position) - along(symbol.max)).max(0.0);
+        let centre_distance = |symbol: &OcrSymbol| ((along(symbol.min) + along(symbol.max)) / 2.0 - along(position)).abs();
+        return self.symbols.iter().enumerate()
+            .min_by(|lhs, rhs| distance(lhs.1).total_cmp(&distance(rhs.1)).then(centre_distance(lhs.1).total_cmp(&centre_distance(rhs.1))))
+            .map(|(index, _)| index);
+    }
+
+    pub fn is_within_bounds(&self, position: &PixelPoint) -> bool {
+        return position.x > self.min.x && position.x <= self.max.x
+            && position.y > self.min.y && position.y <= self.max.y;
     }
 
     pub fn is_highlighted(&self) -> bool {
@@ -181,35 +455,75 @@ impl PresentableWord {
         return was_highlighted != is_highlighted; //return true if value has changed
     }
 
-    fn to_text(&self, scale: PxScale) -> OwnedText {
+    fn to_text(&self) -> OwnedText {
+        let text = match self.is_vertical {
+            true => self.text.chars().map(|char| format!("{}\n", char)).collect(), //One character per row
+            false => self.text.clone(),
+        };
         return OwnedText::default()
-            .with_text(&self.text)
-            .with_scale(scale)
+            .with_text(&text)
+            .with_scale(self.scale)
             .with_color(self.get_colour())
             .with_font_id(FontId(0));
     }
 
+    fn get_outline_colour(&self) -> [f32; 3] {
+        if self.is_highlighted {
+            return [0.0, 1.0, 0.0]; //green
+        } else if self.is_dictionary_corrected() {
+            return [1.0, 0.5, 0.0]; //orange
+        } else if self.confidence < CONFIDENCE_THRESHOLD {
+            return [1.0, 0.0, 0.0]; //red
+        } else {
+            return [0.3, 0.6, 1.0]; //blue
+        }
+    }
+
     fn get_colour(&self) -> [f32; 4] {
         if self.is_highlighted {
             return [0.0, 1.0, 0.0, 1.0]; //green
-        } else if self.confidence < 90.0 {
+        } else if self.is_dictionary_corrected() { //Corrected symbols keep their low confidence, so are checked first
+            return [1.0, 0.5, 0.0, 1.0]; //orange
+        } else if self.confidence < CONFIDENCE_THRESHOLD {
             return [1.0, 0.0, 0.0, 1.0]; //red
         } else {
             return [0.0, 0.0, 0.0, 1.0]; //black
         }
     }
 
-    pub fn generate_translation_section(&self, glyph_brush: &mut GlyphBrush<()>, language: &SupportedLanguages) -> (OwnedSection, Option<Rect>) {
-        let translations = query_by_chinese(&self.text);
+    /// List the alternative readings of one of the word's symbols, numbered so one can be picked with the keyboard.
+    pub fn generate_choices_section<G: GlyphCruncher>(&self, symbol_index: usize, glyph_brush: &mut G) -> (OwnedSection, Option<Rect>) {
+        let symbol = &self.symbols[symbol_index];
+        let header = match &symbol.raw_text {
+            Some(raw_text) => format!("{} ({:.0}%, read as {}), press a number to replace it:\n", symbol.text, symbol.confidence, raw_text),
+            None => format!("{} ({:.0}%), press a number to replace it:\n", symbol.text, symbol.confidence),
+        };
+        let mut texts = vec![OwnedText::new(&header).with_scale(24.0)];
+        for (index, choice) in symbol.choices.iter().take(MAX_CHOICE_KEYS).enumerate() {
+            texts.push(OwnedText::new(format!("{}. {} ({:.0}%)\n", index + 1, choice.text, choice.confidence))
+                .with_scale(24.0));
+        }
+
+        let section = Section::default()
+            .to_owned()
+            .with_text(texts);
+
+        let bounds = glyph_brush.glyph_bounds(&section);
+
+        (section, bounds)
+    }
+
+    pub fn generate_translation_section<G: GlyphCruncher>(&self, glyph_brush: &mut G, language: &SupportedLanguages) -> (OwnedSection, Option<Rect>) {
+        let translations = dictionary::query(language.get_script(), &self.text);
         let mut translations_as_string = Vec::with_capacity(translations.len());
         for translation in translations {
             let mut translation_as_string = "".to_owned();
-            translation_as_string.push_str(if language == &SupportedLanguages::ChiTra {&translation.traditional} else {&translation.simplified});
-            translation_as_string.push_str("(");
-            translation_as_string.push_str(&translation.pinyin_marks);
+            translation_as_string.push_str(if language.is_traditional() {translation.get_traditional()} else {translation.get_simplified()});
+            translation_as_string.push('(');
+            translation_as_string.push_str(translation.get_reading());
             translation_as_string.push_str("): \t");
-            translation_as_string.push_str(&translation.english.join("\n          "));
-            translation_as_string.push_str("\n");
+            translation_as_string.push_str(&translation.get_english().join("\n          "));
+            translation_as_string.push('\n');
             translations_as_string.push(OwnedText::new(&translation_as_string)
                 .with_scale(24.0));
         }
@@ -224,45 +538,167 @@ impl PresentableWord {
     }
 }
 
+/// How recognized text is drawn over the capture.
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize, ValueEnum)]
+pub enum OverlayLayout {
+    /// Each line is redrawn from its first character onto a white strip.
+    Strip,
+    /// Each word is drawn directly over the original glyphs, covering only the ink it was recognized from.
+    InPlace,
+    /// Nothing covers the game's own text, and each word is only underlined, or outlined while hovered.
+    Outline,
+}
+
 pub struct PresentableLine {
     words: Vec<PresentableWord>,
-    section: OwnedSection,
+    sections: Vec<OwnedSection>,
+    layout: OverlayLayout,
     min: PixelPoint,
     max: PixelPoint,
     scale: PxScale,
+    is_vertical: bool,
 }
 
 impl PresentableLine {
-    pub fn from_hocr(hocr_words: Vec<HocrWord>, glyph_brush: &mut GlyphBrush<()>) -> Self {
+    /// Lay out a line in the given layout, as a column read from top to bottom when `is_vertical` is set.
+    pub fn from_hocr_with_layout<G: GlyphCruncher>(hocr_words: Vec<HocrWord>, is_vertical: bool, layout: OverlayLayout, glyph_brush: &mut G) -> Self {
+        match layout {
+            OverlayLayout::Strip if is_vertical => Self::from_hocr_vertical(hocr_words, glyph_brush),
+            OverlayLayout::Strip => Self::from_hocr(hocr_words, glyph_brush),
+            OverlayLayout::InPlace => Self::from_hocr_in_place(hocr_words, is_vertical, glyph_brush),
+            OverlayLayout::Outline => {
+                let mut line = Self::from_hocr_in_place(hocr_words, is_vertical, glyph_brush);
+                line.layout = OverlayLayout::Outline;
+                line.sections = line.build_sections();
+                line
+            },
+        }
+    }
+
+    pub fn from_hocr<G: GlyphCruncher>(hocr_words: Vec<HocrWord>, glyph_brush: &mut G) -> Self {
         let scale = PxScale::from(hocr_words.iter()
             .filter(|word| !word.text.starts_with(|char: char| char.is_ascii_punctuation()))
             .map(|word| word.get_scale())
             .sum::<f32>() / hocr_words.len() as f32); //average scale of non-punctuation characters
         let min = hocr_words[0].get_min();
         let mut presentable_words = Vec::with_capacity(hocr_words.len());
-        let mut accumulated_text = Vec::with_capacity(hocr_words.len());
         let mut offset = min;
         for hocr_word in hocr_words {
-            let presentable_word = PresentableWord::new(hocr_word.text, offset, hocr_word.confidence);
-            let text = presentable_word.clone().to_text(scale);
-            presentable_words.push(presentable_word);
-            let word_bounds = glyph_brush.glyph_bounds(&OwnedSection::<()>::default().with_text(vec![text.clone()]).with_screen_position(offset)).unwrap();
-            accumulated_text.push(text);
+            let text = OwnedText::new(&hocr_word.text).with_scale(scale).with_font_id(FontId(0));
+            let word_bounds = glyph_brush.glyph_bounds(&OwnedSection::<()>::default().with_text(vec![text]).with_screen_position(offset)).unwrap();
+            presentable_words.push(PresentableWord::new(hocr_word.text, offset, PixelPoint::new(word_bounds.max.x, offset.y + scale.y), scale, hocr_word.confidence, hocr_word.symbols));
             offset = PixelPoint::new(word_bounds.max.x, word_bounds.min.y);
         }
         let section = OwnedSection::<()>::default()
                 .with_screen_position(min)
-                .with_text(accumulated_text);
+                .with_text(presentable_words.iter().map(|word| word.to_text()).collect());
 
         let line_bounds = glyph_brush.glyph_bounds(&section).unwrap();
         let max: PixelPoint = PixelPoint::from(line_bounds.max);
 
         return Self {
             words: presentable_words,
-            section,
+            sections: vec![section],
+            layout: OverlayLayout::Strip,
             min,
             max,
             scale,
+            is_vertical: false,
+        }
+    }
+
+    /// Redraw a column from its first character downwards onto a white strip, with each word stacked one character
+    /// per row.
+    pub fn from_hocr_vertical<G: GlyphCruncher>(hocr_words: Vec<HocrWord>, glyph_brush: &mut G) -> Self {
+        let scale = PxScale::from(hocr_words.iter()
+            .filter(|word| !word.text.starts_with(|char: char| char.is_ascii_punctuation()))
+            .map(|word| word.get_scale_in(true))
+            .sum::<f32>() / hocr_words.len() as f32); //average scale of non-punctuation characters
+        let min = hocr_words[0].get_min();
+        let mut presentable_words = Vec::with_capacity(hocr_words.len());
+        let mut offset = min;
+        for hocr_word in hocr_words {
+            let presentable_word = PresentableWord::new(hocr_word.text, offset, offset, scale, hocr_word.confidence, hocr_word.symbols)
+                .with_vertical(true);
+            let word_bounds = glyph_brush.glyph_bounds(&OwnedSection::<()>::default().with_text(vec![presentable_word.to_text()]).with_screen_position(offset)).unwrap();
+            presentable_words.push(PresentableWord {
+                max: PixelPoint::new(offset.x + scale.x, word_bounds.max.y),
+                ..presentable_word
+            });
+            offset = PixelPoint::new(offset.x, word_bounds.max.y);
+        }
+        let max = presentable_words.iter().map(|word| word.max).reduce(|lhs, rhs| PixelPoint::new(lhs.x.max(rhs.x), lhs.y.max(rhs.y))).unwrap();
+        let mut line = Self {
+            words: presentable_words,
+            sections: Vec::new(),
+            layout: OverlayLayout::Strip,
+            min,
+            max,
+            scale,
+            is_vertical: true,
+        };
+        line.sections = line.build_sections();
+        return line;
+    }
+
+    /// Lay each word out over its own bounding box, which is only accurate once boxes have been aligned to the ink.
+    /// In a column, characters are sized by their width and each word is stacked one character per row.
+    pub fn from_hocr_in_place<G: GlyphCruncher>(hocr_words: Vec<HocrWord>, is_vertical: bool, glyph_brush: &mut G) -> Self {
+        let character_heights: Vec<f32> = hocr_words.iter()
+            .filter(|word| word.text.starts_with(char::is_alphanumeric))
+            .map(|word| word.get_scale_in(is_vertical))
+            .collect();
+        let line_height = match character_heights.len() {
+            0 => hocr_words.iter().map(|word| word.get_scale_in(is_vertical)).fold(0.0, f32::max),
+            count => character_heights.iter().sum::<f32>() / count as f32,
+        };
+        let mut presentable_words = Vec::with_capacity(hocr_words.len());
+        for hocr_word in hocr_words {
+            //Punctuation has very little ink, so is drawn at the same size as the rest of the line
+            let ink_height = match hocr_word.text.starts_with(char::is_alphanumeric) {
+                true => hocr_word.get_scale_in(is_vertical),
+                false => line_height,
+            };
+            let scale = PxScale::from(ink_height / GLYPH_INK_HEIGHT);
+            let mut presentable_word = PresentableWord::new(hocr_word.text, hocr_word.min, hocr_word.max, scale, hocr_word.confidence, hocr_word.symbols)
+                .with_vertical(is_vertical);
+            //Centre the recognized text over the original glyphs
+            if let Some(bounds) = glyph_brush.glyph_bounds(&OwnedSection::<()>::default().with_text(vec![presentable_word.to_text()])) {
+                let centre_x = (hocr_word.min.x + hocr_word.max.x) / 2.0;
+                let centre_y = (hocr_word.min.y + hocr_word.max.y) / 2.0;
+                presentable_word.text_position = PixelPoint::new(
+                    centre_x - bounds.min.x - bounds.width() / 2.0,
+                    centre_y - bounds.min.y - bounds.height() / 2.0,
+                );
+            }
+            presentable_words.push(presentable_word);
+        }
+        let min = presentable_words.iter().map(|word| word.min).reduce(|lhs, rhs| PixelPoint::new(lhs.x.min(rhs.x), lhs.y.min(rhs.y))).unwrap();
+        let max = presentable_words.iter().map(|word| word.max).reduce(|lhs, rhs| PixelPoint::new(lhs.x.max(rhs.x), lhs.y.max(rhs.y))).unwrap();
+        let mut line = Self {
+            words: presentable_words,
+            sections: Vec::new(),
+            layout: OverlayLayout::InPlace,
+            min,
+            max,
+            scale: PxScale::from(line_height / GLYPH_INK_HEIGHT),
+            is_vertical,
+        };
+        line.sections = line.build_sections();
+        return line;
+    }
+
+    fn build_sections(&self) -> Vec<OwnedSection> {
+        match self.layout {
+            OverlayLayout::Strip if !self.is_vertical => vec![OwnedSection::<()>::default()
+                .with_screen_position(self.min)
+                .with_text(self.words.iter().map(|word| word.to_text()).collect())],
+            OverlayLayout::Strip | OverlayLayout::InPlace => self.words.iter()
+                .map(|word| OwnedSection::<()>::default()
+                    .with_screen_position(word.text_position)
+                    .with_text(vec![word.to_text()]))
+                .collect(),
+            OverlayLayout::Outline => Vec::new(),
         }
     }
 
@@ -270,25 +706,100 @@ impl PresentableLine {
         &self.words
     }
 
+    /// Whether the line is a column read from top to bottom.
+    pub fn is_vertical(&self) -> bool {
+        self.is_vertical
+    }
+
+    pub fn get_text(&self) -> String {
+        self.words.iter().map(|word| word.text.as_str()).collect()
+    }
+
+    /// The text with the words separated by spaces, for languages which put spaces between words.
+    pub fn get_spaced_text(&self) -> String {
+        self.words.iter().map(|word| word.text.as_str()).collect::<Vec<_>>().join(" ")
+    }
+
+    /// The smallest box containing every symbol where it was found in the capture, which for the strip layout
+    /// differs from where the line is drawn.
+    pub fn get_source_bounds(&self) -> (PixelPoint, PixelPoint) {
+        let symbols = self.words.iter().flat_map(|word| word.symbols.iter());
+        let min = symbols.clone().map(|symbol| symbol.min).reduce(|lhs, rhs| PixelPoint::new(lhs.x.min(rhs.x), lhs.y.min(rhs.y)));
+        let max = symbols.map(|symbol| symbol.max).reduce(|lhs, rhs| PixelPoint::new(lhs.x.max(rhs.x), lhs.y.max(rhs.y)));
+        return (min.unwrap_or(self.min), max.unwrap_or(self.max));
+    }
+
+    /// Replace the whole line with text typed by the user and lay it out again, re-tokenizing it.
+    ///
+    /// The new characters are spread evenly along the space the original line took up in the capture.
+    /// In languages which put spaces between words, the words typed are kept rather than re-tokenized.
+    /// Returns `None` when the text is empty or only whitespace, since a line needs at least one character.
+    pub fn with_text<G: GlyphCruncher>(&self, text: &str, language: SupportedLanguages, glyph_brush: &mut G) -> Option<Self> {
+        let (min, max) = self.get_source_bounds();
+        let keep_words = language.get_script().has_word_spaces();
+        let characters: Vec<(usize, char)> = text.split_whitespace()
+            .enumerate()
+            .flat_map(|(word_index, word)| word.chars().map(move |char| (word_index, char)))
+            .collect();
+        if characters.is_empty() {
+            return None;
+        }
+        let length = match self.is_vertical {
+            true => (max.y - min.y) / characters.len() as f32,
+            false => (max.x - min.x) / characters.len() as f32,
+        };
+        let mut words: Vec<HocrWord> = Vec::new();
+        for (index, (word_index, char)) in characters.iter().enumerate() {
+            let (start, end) = (length * index as f32, length * (index + 1) as f32);
+            let (symbol_min, symbol_max) = match self.is_vertical {
+                true => (PixelPoint::new(min.x, min.y + start), PixelPoint::new(max.x, min.y + end)),
+                false => (PixelPoint::new(min.x + start, min.y), PixelPoint::new(min.x + end, max.y)),
+            };
+            let word = HocrWord::from_symbol(OcrSymbol::new(char.to_string(), symbol_min, symbol_max, 100.0, Vec::new()));
+            match words.pop() {
+                Some(previous) if keep_words && index > 0 && characters[index - 1].0 == *word_index => words.push(previous + &word),
+                Some(previous) => words.extend([previous, word]),
+                None => words.push(word),
+            }
+        }
+        return Some(Self::from_hocr_with_layout(ocr::group_into_tokens(words, language), self.is_vertical, self.layout, glyph_brush));
+    }
+
+    /// Replace one symbol's text and lay the line out again, re-tokenizing it so dictionary lookups use the corrected text.
+    pub fn with_correction<G: GlyphCruncher>(&self, word_index: usize, symbol_index: usize, text: String, language: SupportedLanguages, glyph_brush: &mut G) -> Self {
+        let mut words = Vec::new();
+        for (i, word) in self.words.iter().enumerate() {
+            let mut symbols = Vec::new();
+            for (j, symbol) in word.symbols.iter().enumerate() {
+                match (i, j) == (word_index, symbol_index) {
+                    true => symbols.push(symbol.corrected(text.clone())),
+                    false => symbols.push(symbol.clone()),
+                }
+            }
+            let symbol_words = symbols.into_iter().map(HocrWord::from_symbol);
+            match language.get_script().has_word_spaces() {
+                true => words.extend(symbol_words.reduce(|lhs, rhs| lhs + &rhs)),
+                false => words.extend(symbol_words),
+            }
+        }
+        return Self::from_hocr_with_layout(ocr::group_into_tokens(words, language), self.is_vertical, self.layout, glyph_brush);
+    }
+
     fn get_mut_words(&mut self) -> &mut Vec<PresentableWord> {
         &mut self.words
     }
 
     pub fn handle_cursor(&mut self, cursor_position: &PixelPoint) {
-        let scale = self.scale;
         let mut is_changed = false;
         for word in self.get_mut_words() {
-            if word.is_within_bounds(cursor_position, scale) {
+            if word.is_within_bounds(cursor_position) {
                 is_changed = word.set_highlighted(true) || is_changed;
             } else {
                 is_changed = word.set_highlighted(false) || is_changed;
             }
         }
         if is_changed {
-            let text = self.words.iter().map(|word| word.to_text(scale)).collect();
-            self.section = OwnedSection::<()>::default()
-                .with_screen_position(self.min)
-                .with_text(text);
+            self.sections = self.build_sections();
         }
     }
 
@@ -304,36 +815,191 @@ impl PresentableLine {
         self.scale
     }
 
-    pub fn get_section(&self) -> &OwnedSection {
-        &self.section
+    pub fn get_sections(&self) -> &Vec<OwnedSection> {
+        &self.sections
     }
 
-    pub fn generate_bounding_vertices(&self, screen_max_point: PixelPoint, offset: u32) -> (Vec<Vertex>, Vec<u32>) {
-        let min = self.get_min().to_normalized_coordinate(screen_max_point);
-        let max = self.get_max().to_normalized_coordinate(screen_max_point);
+    pub fn get_layout(&self) -> OverlayLayout {
+        self.layout
+    }
 
-        let verticies = vec![
-            Vertex { //top left
-                position: min.clone(),
-                color: [1.0, 1.0, 1.0],
-            },
-            Vertex { //top right
-                position: [max[0], min[1]].clone(),
-                color: [1.0, 1.0, 1.0],
-            },
-            Vertex { //bottom left
-                position: [min[0], max[1]].clone(),
-                color: [1.0, 1.0, 1.0],
+    pub fn generate_bounding_vertices(&self, screen_max_point: PixelPoint, offset: u32) -> (Vec<Vertex>, Vec<u32>) {
+        match self.layout {
+            OverlayLayout::Strip => quad_vertices(self.get_min(), self.get_max(), BACKGROUND_COLOUR, screen_max_point, offset),
+            OverlayLayout::InPlace => {
+                let mut verticies = Vec::with_capacity(self.words.len() * 4);
+                let mut indices = Vec::with_capacity(self.words.len() * 6);
+                for word in &self.words {
+                    //Pad slightly so the edges of the original glyphs don't show around the redrawn text
+                    let padding = PixelPoint::new(IN_PLACE_PADDING, IN_PLACE_PADDING);
+                    let (mut word_verticies, mut word_indices) = quad_vertices(word.min - padding, word.max + padding, BACKGROUND_COLOUR, screen_max_point, offset + verticies.len() as u32);
+                    verticies.append(&mut word_verticies);
+                    indices.append(&mut word_indices);
+                }
+                (verticies, indices)
             },
-            Vertex { //bottom right
-                position: max.clone(),
-                color: [1.0, 1.0, 1.0],
+            OverlayLayout::Outline => {
+                let mut verticies = Vec::new();
+                let mut indices = Vec::new();
+                for word in &self.words {
+                    let colour = word.get_outline_colour();
+                    let thickness = PixelPoint::new(OUTLINE_THICKNESS, OUTLINE_THICKNESS);
+                    let bottom_left = PixelPoint::new(word.min.x, word.max.y);
+                    let top_right = PixelPoint::new(word.max.x, word.min.y);
+                    let bottom = (bottom_left, word.max + thickness);
+                    let right = (top_right, word.max + thickness);
+                    //Columns are marked with a line down their right hand side rather than underneath
+                    let (mark, other) = match word.is_vertical {
+                        true => (right, bottom),
+                        false => (bottom, right),
+                    };
+                    let mut edges = vec![mark];
+                    if word.is_highlighted {
+                        edges.push((word.min - thickness, top_right)); //top
+                        edges.push((word.min - thickness, bottom_left + PixelPoint::new(0.0, OUTLINE_THICKNESS))); //left
+                        edges.push(other);
+                    }
+                    for (min, max) in edges {
+                        let (mut edge_verticies, mut edge_indices) = quad_vertices(min, max, colour, screen_max_point, offset + verticies.len() as u32);
+                        verticies.append(&mut edge_verticies);
+                        indices.append(&mut edge_indices);
+                    }
+                }
+                (verticies, indices)
             },
-        ];
-        let indices = vec![
-            offset + 0, offset + 1, offset + 2,
-            offset + 2, offset + 1, offset + 3
-        ];
-        return (verticies, indices);
-    }
-}
\ No newline at end of file
+        }
+    }
+}
+
+/// A solid rectangle covering the given area, as two triangles.
+fn quad_vertices(min: PixelPoint, max: PixelPoint, colour: [f32; 3], screen_max_point: PixelPoint, offset: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let min = min.to_normalized_coordinate(screen_max_point);
+    let max = max.to_normalized_coordinate(screen_max_point);
+
+    let verticies = vec![
+        Vertex { //top left
+            position: min,
+            color: colour,
+        },
+        Vertex { //top right
+            position: [max[0], min[1]],
+            color: colour,
+        },
+        Vertex { //bottom left
+            position: [min[0], max[1]],
+            color: colour,
+        },
+        Vertex { //bottom right
+            position: max,
+            color: colour,
+        },
+    ];
+    let indices = vec![
+        offset, offset + 1, offset + 2,
+        offset + 2, offset + 1, offset + 3
+    ];
+    return (verticies, indices);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glyph_brush::{GlyphCalculator, GlyphCalculatorBuilder, ab_glyph::FontArc};
+    use crate::dictionary::IsolatedDictionaries;
+
+    fn symbol(text: &str, x0: f32, y0: f32, x1: f32, y1: f32) -> OcrSymbol {
+        OcrSymbol::new(text.to_string(), PixelPoint::new(x0, y0), PixelPoint::new(x1, y1), 95.0, Vec::new())
+    }
+
+    fn word(min: PixelPoint, max: PixelPoint, symbols: Vec<OcrSymbol>) -> PresentableWord {
+        let text = symbols.iter().map(|symbol| symbol.get_text().as_str()).collect();
+        PresentableWord::new(text, min, max, PxScale::from(20.0), 95.0, symbols)
+    }
+
+    fn glyph_calculator() -> GlyphCalculator {
+        GlyphCalculatorBuilder::using_font(FontArc::try_from_slice(include_bytes!("Inconsolata-Regular.ttf")).unwrap()).build()
+    }
+
+    fn line_text(line: &PresentableLine) -> String {
+        line.get_words().iter().map(|word| word.get_text().as_str()).collect()
+    }
+
+    #[test]
+    fn with_text_needs_at_least_one_character() {
+        let _dictionaries = IsolatedDictionaries::new();
+        let glyph_calculator = glyph_calculator();
+        let mut glyph_cruncher = glyph_calculator.cache_scope();
+        let words = vec![HocrWord::from_symbol(symbol("你", 0.0, 0.0, 20.0, 20.0)), HocrWord::from_symbol(symbol("妤", 20.0, 0.0, 40.0, 20.0))];
+        for layout in [OverlayLayout::Strip, OverlayLayout::InPlace, OverlayLayout::Outline] {
+            for is_vertical in [false, true] {
+                let line = PresentableLine::from_hocr_with_layout(words.clone(), is_vertical, layout, &mut glyph_cruncher);
+                assert!(line.with_text("", SupportedLanguages::ChiSim, &mut glyph_cruncher).is_none());
+                assert!(line.with_text(" \t\n", SupportedLanguages::ChiSim, &mut glyph_cruncher).is_none());
+                let retyped = line.with_text(" 你好 ", SupportedLanguages::ChiSim, &mut glyph_cruncher).unwrap();
+                assert_eq!(line_text(&retyped), "你好");
+                assert_eq!(retyped.is_vertical(), is_vertical);
+            }
+        }
+    }
+
+    #[test]
+    fn symbol_at_uses_each_symbols_box() {
+        let symbols = vec![symbol("一", 0.0, 0.0, 10.0, 20.0), symbol("鬱", 10.0, 0.0, 40.0, 20.0)];
+        let word = word(PixelPoint::new(0.0, 0.0), PixelPoint::new(40.0, 20.0), symbols);
+        assert_eq!(word.symbol_at(&PixelPoint::new(5.0, 10.0)), Some(0));
+        assert_eq!(word.symbol_at(&PixelPoint::new(15.0, 10.0)), Some(1));
+        assert_eq!(word.symbol_at(&PixelPoint::new(50.0, 10.0)), None);
+    }
+
+    #[test]
+    fn symbol_at_picks_nearest_symbol_in_gaps() {
+        let symbols = vec![symbol("你", 0.0, 0.0, 10.0, 20.0), symbol("好", 30.0, 0.0, 40.0, 20.0)];
+        let word = word(PixelPoint::new(0.0, 0.0), PixelPoint::new(40.0, 20.0), symbols);
+        assert_eq!(word.symbol_at(&PixelPoint::new(14.0, 10.0)), Some(0));
+        assert_eq!(word.symbol_at(&PixelPoint::new(26.0, 10.0)), Some(1));
+    }
+
+    #[test]
+    fn symbol_at_maps_words_drawn_elsewhere() {
+        let symbols = vec![symbol("一", 0.0, 0.0, 10.0, 20.0), symbol("鬱", 10.0, 0.0, 40.0, 20.0)];
+        let word = word(PixelPoint::new(100.0, 50.0), PixelPoint::new(180.0, 90.0), symbols);
+        assert_eq!(word.symbol_at(&PixelPoint::new(110.0, 70.0)), Some(0));
+        assert_eq!(word.symbol_at(&PixelPoint::new(130.0, 70.0)), Some(1));
+    }
+
+    #[test]
+    fn symbol_at_follows_columns() {
+        let symbols = vec![symbol("一", 0.0, 0.0, 20.0, 10.0), symbol("鬱", 0.0, 10.0, 20.0, 40.0)];
+        let word = word(PixelPoint::new(0.0, 0.0), PixelPoint::new(20.0, 40.0), symbols).with_vertical(true);
+        assert_eq!(word.symbol_at(&PixelPoint::new(10.0, 5.0)), Some(0));
+        assert_eq!(word.symbol_at(&PixelPoint::new(10.0, 15.0)), Some(1));
+    }
+
+    #[test]
+    fn draws_dictionary_corrections_differently_from_the_engines_reading() {
+        let choices = vec![SymbolChoice::new("好".to_string(), 35.0)];
+        let uncertain = OcrSymbol::new("妤".to_string(), PixelPoint::new(20.0, 0.0), PixelPoint::new(40.0, 20.0), 40.0, choices);
+        let corrected = uncertain.with_dictionary_choice("好".to_string(), 35.0);
+        let (min, max) = (PixelPoint::new(0.0, 0.0), PixelPoint::new(40.0, 20.0));
+        let raw_word = PresentableWord::new("你妤".to_string(), min, max, PxScale::from(20.0), 40.0, vec![symbol("你", 0.0, 0.0, 20.0, 20.0), uncertain]);
+        let mut corrected_word = PresentableWord::new("你好".to_string(), min, max, PxScale::from(20.0), 35.0, vec![symbol("你", 0.0, 0.0, 20.0, 20.0), corrected]);
+        assert!(!raw_word.is_dictionary_corrected());
+        assert!(corrected_word.is_dictionary_corrected());
+
+        //Still uncertain, but orange rather than red so the change stands out
+        assert_eq!(raw_word.to_text().extra.color, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(raw_word.get_outline_colour(), [1.0, 0.0, 0.0]);
+        assert_eq!(corrected_word.to_text().extra.color, [1.0, 0.5, 0.0, 1.0]);
+        assert_eq!(corrected_word.get_outline_colour(), [1.0, 0.5, 0.0]);
+        corrected_word.set_highlighted(true);
+        assert_eq!(corrected_word.to_text().extra.color, [0.0, 1.0, 0.0, 1.0]);
+
+        //Clicking the symbol shows what the engine originally read
+        let glyph_calculator = glyph_calculator();
+        let mut glyph_cruncher = glyph_calculator.cache_scope();
+        let (section, _) = corrected_word.generate_choices_section(1, &mut glyph_cruncher);
+        assert_eq!(section.text[0].text, "好 (35%, read as 妤), press a number to replace it:\n");
+        let (section, _) = raw_word.generate_choices_section(1, &mut glyph_cruncher);
+        assert_eq!(section.text[0].text, "妤 (40%), press a number to replace it:\n");
+    }
+}
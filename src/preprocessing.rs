@@ -0,0 +1,691 @@
+use std::fmt;
+
+use image::{DynamicImage, GrayImage, Luma, imageops::{BiLevel, FilterType, dither}};
+use serde::Serialize;
+use thiserror::Error;
+
+pub const DEFAULT_PROFILE: &str = "default";
+const DEFAULT_STEPS: &str = "resize(4, catmullrom), blur(0.9), dither";
+
+#[derive(Debug, Error)]
+pub enum PreprocessingError {
+    #[error("Unknown preprocessing step {0:?}")]
+    UnknownStep(String),
+    #[error("Preprocessing step {step:?} is missing an argument")]
+    MissingArgument { step: String },
+    #[error("Invalid argument {argument:?} for preprocessing step {step:?}")]
+    InvalidArgument { step: String, argument: String },
+    #[error("Unbalanced parentheses in preprocessing steps {0:?}")]
+    UnbalancedParentheses(String),
+}
+
+/// A single stage of image preprocessing before the image is handed to the OCR engine.
+///
+/// Morphology steps treat dark pixels as text, so `dilate` thickens glyphs and `erode` thins them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PreprocessingStep {
+    Resize { scale: f32, filter: FilterType },
+    Blur { sigma: f32 },
+    Grayscale,
+    ContrastStretch { low_percentile: f32, high_percentile: f32 },
+    Invert,
+    ColourKey { colour: [u8; 3], tolerance: u8 },
+    Dither,
+    Threshold { level: u8 },
+    Otsu,
+    AdaptiveThreshold { radius: u32, offset: i32 },
+    Dilate { radius: u32 },
+    Erode { radius: u32 },
+    Open { radius: u32 },
+    Close { radius: u32 },
+}
+
+/// Whether a captured image has dark text on a light background, which is what Tesseract expects, or the reverse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Polarity {
+    DarkOnLight,
+    LightOnDark,
+}
+
+impl fmt::Display for Polarity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DarkOnLight => write!(f, "dark text on light"),
+            Self::LightOnDark => write!(f, "light text on dark"),
+        }
+    }
+}
+
+/// An ordered list of preprocessing steps, parsed from a string such as `resize(4, catmullrom), blur(0.9), dither`.
+///
+/// When `auto_invert` is set, images detected as light text on a dark background are inverted before any steps run,
+/// unless the steps already handle polarity themselves with `invert` or `colour_key`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreprocessingPipeline {
+    steps: Vec<PreprocessingStep>,
+    auto_invert: bool,
+}
+
+impl Default for PreprocessingPipeline {
+    fn default() -> Self {
+        Self::parse(DEFAULT_STEPS).unwrap()
+    }
+}
+
+impl PreprocessingPipeline {
+    pub fn new(steps: Vec<PreprocessingStep>) -> Self {
+        Self {
+            steps,
+            auto_invert: true,
+        }
+    }
+
+    pub fn with_auto_invert(mut self, auto_invert: bool) -> Self {
+        self.auto_invert = auto_invert;
+        self
+    }
+
+    pub fn parse(steps: &str) -> Result<Self, PreprocessingError> {
+        let steps = split_top_level(steps)?
+            .into_iter()
+            .filter(|step| !step.is_empty())
+            .map(PreprocessingStep::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok(Self::new(steps));
+    }
+
+    pub fn get_steps(&self) -> &Vec<PreprocessingStep> {
+        &self.steps
+    }
+
+    pub fn get_auto_invert(&self) -> bool {
+        self.auto_invert
+    }
+
+    /// Run every step in order, always producing a grayscale image for the OCR engine.
+    /// Also returns the polarity detected in the original image, which is what the capture looked like rather than what
+    /// the OCR engine is given, since the image may since have been inverted.
+    pub fn apply(&self, image: &DynamicImage) -> (GrayImage, Polarity) {
+        self.apply_with_stages(image, |_name, _image| {})
+    }
+
+    /// As `apply`, calling `on_stage` with the image produced by every step along the way.
+    pub fn apply_with_stages<F: FnMut(&str, &DynamicImage)>(&self, image: &DynamicImage, mut on_stage: F) -> (GrayImage, Polarity) {
+        let polarity = detect_polarity(image);
+        let mut image = image.clone();
+        let handles_polarity = self.steps.iter()
+            .any(|step| matches!(step, PreprocessingStep::Invert | PreprocessingStep::ColourKey { .. }));
+        if self.auto_invert && !handles_polarity && polarity == Polarity::LightOnDark {
+            image.invert();
+            on_stage("auto_invert", &image);
+        }
+        for step in &self.steps {
+            image = step.apply(image);
+            on_stage(step.get_name(), &image);
+        }
+        return (image.into_luma8(), polarity);
+    }
+}
+
+/// Estimate the background brightness from the image border, where text rarely reaches,
+/// and from the median of the whole image, where background pixels usually outnumber text.
+pub fn detect_polarity(image: &DynamicImage) -> Polarity {
+    let image = image.to_luma8();
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Polarity::DarkOnLight;
+    }
+    let border = (width.min(height) / 20).max(1);
+    let border_pixels = image.enumerate_pixels()
+        .filter(|(x, y, _pixel)| *x < border || *y < border || *x >= width - border || *y >= height - border)
+        .map(|(_x, _y, pixel)| pixel.0[0])
+        .collect::<Vec<_>>();
+    let background = (median(&border_pixels) as u32 + median(image.as_raw()) as u32) / 2;
+    if background < 128 {
+        return Polarity::LightOnDark;
+    }
+    return Polarity::DarkOnLight;
+}
+
+fn median(values: &[u8]) -> u8 {
+    let mut counts = [0usize; 256];
+    for value in values {
+        counts[*value as usize] += 1;
+    }
+    let mut accumulated = 0;
+    for (level, count) in counts.iter().enumerate() {
+        accumulated += count;
+        if accumulated * 2 >= values.len() {
+            return level as u8;
+        }
+    }
+    return 255;
+}
+
+impl fmt::Display for PreprocessingPipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let steps = self.steps.iter().map(|step| step.to_string()).collect::<Vec<_>>();
+        write!(f, "{}", steps.join(", "))
+    }
+}
+
+impl PreprocessingStep {
+    fn parse(step: &str) -> Result<Self, PreprocessingError> {
+        let (name, arguments) = match step.find('(') {
+            Some(index) => {
+                if !step.ends_with(')') {
+                    return Err(PreprocessingError::UnbalancedParentheses(step.to_string()));
+                }
+                let arguments = step[index + 1 .. step.len() - 1].split(',')
+                    .map(|argument| argument.trim())
+                    .filter(|argument| !argument.is_empty())
+                    .collect::<Vec<_>>();
+                (step[..index].trim(), arguments)
+            },
+            None => (step.trim(), Vec::new()),
+        };
+        let arguments = Arguments { step: name, arguments };
+        let step = match name.to_lowercase().as_str() {
+            "resize" => Self::Resize {
+                scale: arguments.required_with(0, parse_positive)?,
+                filter: arguments.optional_with(1, parse_filter)?.unwrap_or(FilterType::CatmullRom),
+            },
+            "blur" => Self::Blur { sigma: arguments.required_with(0, parse_positive)? },
+            "grayscale" | "luma" => Self::Grayscale,
+            "contrast_stretch" => Self::ContrastStretch {
+                low_percentile: arguments.optional(0)?.unwrap_or(1.0),
+                high_percentile: arguments.optional(1)?.unwrap_or(99.0),
+            },
+            "invert" => Self::Invert,
+            "colour_key" | "color_key" => Self::ColourKey {
+                colour: arguments.required_with(0, parse_colour)?,
+                tolerance: arguments.optional(1)?.unwrap_or(60),
+            },
+            "dither" => Self::Dither,
+            "threshold" => Self::Threshold { level: arguments.optional(0)?.unwrap_or(128) },
+            "otsu" => Self::Otsu,
+            "adaptive_threshold" => Self::AdaptiveThreshold {
+                radius: arguments.optional(0)?.unwrap_or(15),
+                offset: arguments.optional(1)?.unwrap_or(10),
+            },
+            "dilate" => Self::Dilate { radius: arguments.optional(0)?.unwrap_or(1) },
+            "erode" => Self::Erode { radius: arguments.optional(0)?.unwrap_or(1) },
+            "open" => Self::Open { radius: arguments.optional(0)?.unwrap_or(1) },
+            "close" => Self::Close { radius: arguments.optional(0)?.unwrap_or(1) },
+            _ => return Err(PreprocessingError::UnknownStep(name.to_string())),
+        };
+        return Ok(step);
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Resize { .. } => "resize",
+            Self::Blur { .. } => "blur",
+            Self::Grayscale => "grayscale",
+            Self::ContrastStretch { .. } => "contrast_stretch",
+            Self::Invert => "invert",
+            Self::ColourKey { .. } => "colour_key",
+            Self::Dither => "dither",
+            Self::Threshold { .. } => "threshold",
+            Self::Otsu => "otsu",
+            Self::AdaptiveThreshold { .. } => "adaptive_threshold",
+            Self::Dilate { .. } => "dilate",
+            Self::Erode { .. } => "erode",
+            Self::Open { .. } => "open",
+            Self::Close { .. } => "close",
+        }
+    }
+
+    fn apply(&self, image: DynamicImage) -> DynamicImage {
+        match *self {
+            Self::Resize { scale, filter } => {
+                let width = ((image.width() as f32 * scale).round() as u32).max(1);
+                let height = ((image.height() as f32 * scale).round() as u32).max(1);
+                image.resize_exact(width, height, filter)
+            },
+            Self::Blur { sigma } => image.blur(sigma),
+            Self::Grayscale => DynamicImage::ImageLuma8(image.into_luma8()),
+            Self::ContrastStretch { low_percentile, high_percentile } => {
+                let mut image = image.into_luma8();
+                contrast_stretch(&mut image, low_percentile, high_percentile);
+                DynamicImage::ImageLuma8(image)
+            },
+            Self::Invert => {
+                let mut image = image;
+                image.invert();
+                image
+            },
+            Self::ColourKey { colour, tolerance } => DynamicImage::ImageLuma8(colour_key(&image, colour, tolerance)),
+            Self::Dither => {
+                let mut image = image.into_luma8();
+                dither(&mut image, &BiLevel);
+                DynamicImage::ImageLuma8(image)
+            },
+            Self::Threshold { level } => {
+                let mut image = image.into_luma8();
+                threshold(&mut image, level);
+                DynamicImage::ImageLuma8(image)
+            },
+            Self::Otsu => {
+                let mut image = image.into_luma8();
+                let level = otsu_level(&image);
+                threshold(&mut image, level);
+                DynamicImage::ImageLuma8(image)
+            },
+            Self::AdaptiveThreshold { radius, offset } => DynamicImage::ImageLuma8(adaptive_threshold(&image.into_luma8(), radius, offset)),
+            Self::Dilate { radius } => DynamicImage::ImageLuma8(grow_dark(&image.into_luma8(), radius)),
+            Self::Erode { radius } => DynamicImage::ImageLuma8(shrink_dark(&image.into_luma8(), radius)),
+            Self::Open { radius } => DynamicImage::ImageLuma8(grow_dark(&shrink_dark(&image.into_luma8(), radius), radius)),
+            Self::Close { radius } => DynamicImage::ImageLuma8(shrink_dark(&grow_dark(&image.into_luma8(), radius), radius)),
+        }
+    }
+}
+
+impl fmt::Display for PreprocessingStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Resize { scale, filter } => write!(f, "resize({}, {})", scale, filter_name(*filter)),
+            Self::Blur { sigma } => write!(f, "blur({})", sigma),
+            Self::Grayscale => write!(f, "grayscale"),
+            Self::ContrastStretch { low_percentile, high_percentile } => write!(f, "contrast_stretch({}, {})", low_percentile, high_percentile),
+            Self::Invert => write!(f, "invert"),
+            Self::ColourKey { colour, tolerance } => write!(f, "colour_key(#{:02x}{:02x}{:02x}, {})", colour[0], colour[1], colour[2], tolerance),
+            Self::Dither => write!(f, "dither"),
+            Self::Threshold { level } => write!(f, "threshold({})", level),
+            Self::Otsu => write!(f, "otsu"),
+            Self::AdaptiveThreshold { radius, offset } => write!(f, "adaptive_threshold({}, {})", radius, offset),
+            Self::Dilate { radius } => write!(f, "dilate({})", radius),
+            Self::Erode { radius } => write!(f, "erode({})", radius),
+            Self::Open { radius } => write!(f, "open({})", radius),
+            Self::Close { radius } => write!(f, "close({})", radius),
+        }
+    }
+}
+
+struct Arguments<'a> {
+    step: &'a str,
+    arguments: Vec<&'a str>,
+}
+
+impl<'a> Arguments<'a> {
+    fn invalid(&self, argument: &str) -> PreprocessingError {
+        PreprocessingError::InvalidArgument { step: self.step.to_string(), argument: argument.to_string() }
+    }
+
+    fn optional_with<T>(&self, index: usize, parse: fn(&str) -> Option<T>) -> Result<Option<T>, PreprocessingError> {
+        match self.arguments.get(index) {
+            Some(argument) => parse(argument).map(Some).ok_or_else(|| self.invalid(argument)),
+            None => Ok(None),
+        }
+    }
+
+    fn required_with<T>(&self, index: usize, parse: fn(&str) -> Option<T>) -> Result<T, PreprocessingError> {
+        self.optional_with(index, parse)?
+            .ok_or_else(|| PreprocessingError::MissingArgument { step: self.step.to_string() })
+    }
+
+    fn optional<T: std::str::FromStr>(&self, index: usize) -> Result<Option<T>, PreprocessingError> {
+        self.optional_with(index, |argument| argument.parse::<T>().ok())
+    }
+}
+
+/// Split on commas which aren't inside a step's parentheses.
+fn split_top_level(steps: &str) -> Result<Vec<&str>, PreprocessingError> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, char) in steps.char_indices() {
+        match char {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err(PreprocessingError::UnbalancedParentheses(steps.to_string())),
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(steps[start..i].trim());
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    if depth != 0 {
+        return Err(PreprocessingError::UnbalancedParentheses(steps.to_string()));
+    }
+    parts.push(steps[start..].trim());
+    return Ok(parts);
+}
+
+/// A finite number greater than zero, for scales and sizes where zero or less would be meaningless.
+fn parse_positive(number: &str) -> Option<f32> {
+    number.parse::<f32>().ok().filter(|number| number.is_finite() && *number > 0.0)
+}
+
+fn parse_filter(filter: &str) -> Option<FilterType> {
+    match filter.to_lowercase().as_str() {
+        "nearest" => Some(FilterType::Nearest),
+        "triangle" => Some(FilterType::Triangle),
+        "catmullrom" => Some(FilterType::CatmullRom),
+        "gaussian" => Some(FilterType::Gaussian),
+        "lanczos3" => Some(FilterType::Lanczos3),
+        _ => None,
+    }
+}
+
+fn filter_name(filter: FilterType) -> &'static str {
+    match filter {
+        FilterType::Nearest => "nearest",
+        FilterType::Triangle => "triangle",
+        FilterType::CatmullRom => "catmullrom",
+        FilterType::Gaussian => "gaussian",
+        FilterType::Lanczos3 => "lanczos3",
+    }
+}
+
+fn parse_colour(colour: &str) -> Option<[u8; 3]> {
+    let hex = colour.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    return Some([channel(0)?, channel(2)?, channel(4)?]);
+}
+
+fn threshold(image: &mut GrayImage, level: u8) {
+    for pixel in image.pixels_mut() {
+        pixel.0[0] = if pixel.0[0] >= level { 255 } else { 0 };
+    }
+}
+
+fn histogram(image: &GrayImage) -> [u32; 256] {
+    let mut histogram = [0u32; 256];
+    for pixel in image.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+    return histogram;
+}
+
+/// Find the threshold which maximizes the variance between the light and dark classes of pixels.
+fn otsu_level(image: &GrayImage) -> u8 {
+    let histogram = histogram(image);
+    let total = image.len() as f64;
+    let sum_all: f64 = histogram.iter().enumerate().map(|(level, count)| level as f64 * *count as f64).sum();
+    let mut sum_background = 0.0;
+    let mut weight_background = 0.0;
+    let mut best_level = 0;
+    let mut best_variance = 0.0;
+    for (level, count) in histogram.iter().enumerate() {
+        weight_background += *count as f64;
+        if weight_background == 0.0 {
+            continue;
+        }
+        let weight_foreground = total - weight_background;
+        if weight_foreground == 0.0 {
+            break;
+        }
+        sum_background += level as f64 * *count as f64;
+        let mean_background = sum_background / weight_background;
+        let mean_foreground = (sum_all - sum_background) / weight_foreground;
+        let variance = weight_background * weight_foreground * (mean_background - mean_foreground).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best_level = level;
+        }
+    }
+    return (best_level + 1).min(255) as u8; //threshold keeps pixels >= level as white
+}
+
+/// Compare each pixel against the mean of its neighbourhood, which copes with uneven backgrounds.
+fn adaptive_threshold(image: &GrayImage, radius: u32, offset: i32) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let stride = width as usize + 1;
+    let mut integral = vec![0u64; stride * (height as usize + 1)];
+    for y in 0..height as usize {
+        let mut row_sum = 0u64;
+        for x in 0..width as usize {
+            row_sum += image.get_pixel(x as u32, y as u32).0[0] as u64;
+            integral[(y + 1) * stride + x + 1] = integral[y * stride + x + 1] + row_sum;
+        }
+    }
+    return GrayImage::from_fn(width, height, |x, y| {
+        let x0 = x.saturating_sub(radius) as usize;
+        let y0 = y.saturating_sub(radius) as usize;
+        let x1 = (x + radius + 1).min(width) as usize;
+        let y1 = (y + radius + 1).min(height) as usize;
+        let sum = integral[y1 * stride + x1] + integral[y0 * stride + x0] - integral[y0 * stride + x1] - integral[y1 * stride + x0];
+        let mean = sum as i64 / ((x1 - x0) * (y1 - y0)) as i64;
+        let value = image.get_pixel(x, y).0[0] as i64;
+        if value >= mean - offset as i64 { Luma([255]) } else { Luma([0]) }
+    });
+}
+
+/// Linearly stretch the given percentiles of the histogram to the full 0-255 range.
+fn contrast_stretch(image: &mut GrayImage, low_percentile: f32, high_percentile: f32) {
+    let histogram = histogram(image);
+    let total = image.len() as f32;
+    let percentile_level = |percentile: f32| {
+        let target = total * percentile.clamp(0.0, 100.0) / 100.0;
+        let mut accumulated = 0.0;
+        for (level, count) in histogram.iter().enumerate() {
+            accumulated += *count as f32;
+            if accumulated >= target {
+                return level as f32;
+            }
+        }
+        return 255.0;
+    };
+    let low = percentile_level(low_percentile);
+    let high = percentile_level(high_percentile);
+    if high <= low {
+        return;
+    }
+    for pixel in image.pixels_mut() {
+        let stretched = (pixel.0[0] as f32 - low) * 255.0 / (high - low);
+        pixel.0[0] = stretched.clamp(0.0, 255.0) as u8;
+    }
+}
+
+/// Keep only pixels close to the given text colour, drawing them as black text on a white background.
+fn colour_key(image: &DynamicImage, colour: [u8; 3], tolerance: u8) -> GrayImage {
+    let image = image.to_rgb8();
+    return GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y).0;
+        let distance = (0..3).map(|i| pixel[i].abs_diff(colour[i])).max().unwrap();
+        if distance <= tolerance { Luma([0]) } else { Luma([255]) }
+    });
+}
+
+fn grow_dark(image: &GrayImage, radius: u32) -> GrayImage {
+    morphology(image, radius, |values| values.min().unwrap())
+}
+
+fn shrink_dark(image: &GrayImage, radius: u32) -> GrayImage {
+    morphology(image, radius, |values| values.max().unwrap())
+}
+
+fn morphology(image: &GrayImage, radius: u32, select: fn(&mut dyn Iterator<Item = u8>) -> u8) -> GrayImage {
+    let (width, height) = image.dimensions();
+    return GrayImage::from_fn(width, height, |x, y| {
+        let x0 = x.saturating_sub(radius);
+        let y0 = y.saturating_sub(radius);
+        let x1 = (x + radius).min(width - 1);
+        let y1 = (y + radius).min(height - 1);
+        let mut values = (y0..=y1).flat_map(|y| (x0..=x1).map(move |x| (x, y))).map(|(x, y)| image.get_pixel(x, y).0[0]);
+        Luma([select(&mut values)])
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn row(values: &[u8]) -> GrayImage {
+        GrayImage::from_fn(values.len() as u32, 1, |x, _y| Luma([values[x as usize]]))
+    }
+
+    fn values(image: &GrayImage) -> Vec<u8> {
+        image.pixels().map(|pixel| pixel.0[0]).collect()
+    }
+
+    /// A white image with the given pixels drawn in black.
+    fn dots(size: u32, dots: &[(u32, u32)]) -> GrayImage {
+        GrayImage::from_fn(size, size, |x, y| if dots.contains(&(x, y)) { Luma([0]) } else { Luma([255]) })
+    }
+
+    fn black_pixels(image: &GrayImage) -> Vec<(u32, u32)> {
+        image.enumerate_pixels().filter(|(_x, _y, pixel)| pixel.0[0] == 0).map(|(x, y, _pixel)| (x, y)).collect()
+    }
+
+    fn apply_step(step: PreprocessingStep, image: GrayImage) -> GrayImage {
+        step.apply(DynamicImage::ImageLuma8(image)).into_luma8()
+    }
+
+    /// A page of the given background with a block of text in the middle.
+    fn page(background: u8, text: u8) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(40, 20, |x, y| match (10..30).contains(&x) && (6..14).contains(&y) {
+            true => Luma([text]),
+            false => Luma([background]),
+        }))
+    }
+
+    #[test]
+    fn parses_steps_and_arguments() {
+        let pipeline = PreprocessingPipeline::parse("Resize(2, nearest), blur(0.5), color_key(#FFcc00, 30), otsu, dilate").unwrap();
+        assert_eq!(pipeline.get_steps(), &vec![
+            PreprocessingStep::Resize { scale: 2.0, filter: FilterType::Nearest },
+            PreprocessingStep::Blur { sigma: 0.5 },
+            PreprocessingStep::ColourKey { colour: [255, 204, 0], tolerance: 30 },
+            PreprocessingStep::Otsu,
+            PreprocessingStep::Dilate { radius: 1 },
+        ]);
+        assert_eq!(pipeline.to_string(), "resize(2, nearest), blur(0.5), colour_key(#ffcc00, 30), otsu, dilate(1)");
+        assert_eq!(PreprocessingPipeline::parse(&pipeline.to_string()).unwrap(), pipeline);
+        assert_eq!(PreprocessingPipeline::parse(DEFAULT_STEPS).unwrap(), PreprocessingPipeline::default());
+        assert_eq!(PreprocessingPipeline::parse(" , otsu,").unwrap().get_steps(), &vec![PreprocessingStep::Otsu]);
+    }
+
+    #[test]
+    fn splits_only_on_top_level_commas() {
+        assert_eq!(split_top_level("resize(4, catmullrom), adaptive_threshold(15, 10),otsu").unwrap(),
+            vec!["resize(4, catmullrom)", "adaptive_threshold(15, 10)", "otsu"]);
+        assert!(matches!(split_top_level("resize(4, catmullrom"), Err(PreprocessingError::UnbalancedParentheses(_))));
+        assert!(matches!(split_top_level("otsu), blur(1)"), Err(PreprocessingError::UnbalancedParentheses(_))));
+        assert!(matches!(PreprocessingPipeline::parse("blur(1)x"), Err(PreprocessingError::UnbalancedParentheses(step)) if step == "blur(1)x"));
+    }
+
+    #[test]
+    fn rejects_unknown_steps_and_bad_arguments() {
+        assert!(matches!(PreprocessingPipeline::parse("otsu, sharpen(2)"), Err(PreprocessingError::UnknownStep(step)) if step == "sharpen"));
+        assert!(matches!(PreprocessingPipeline::parse("colour_key"), Err(PreprocessingError::MissingArgument { step }) if step == "colour_key"));
+        assert!(matches!(PreprocessingPipeline::parse("blur()"), Err(PreprocessingError::MissingArgument { step }) if step == "blur"));
+        let invalid_argument = |steps: &str| match PreprocessingPipeline::parse(steps) {
+            Err(PreprocessingError::InvalidArgument { step, argument }) => (step, argument),
+            other => panic!("Expected an invalid argument, got {:?}", other),
+        };
+        assert_eq!(invalid_argument("threshold(high)"), ("threshold".to_string(), "high".to_string()));
+        assert_eq!(invalid_argument("threshold(256)"), ("threshold".to_string(), "256".to_string()));
+        assert_eq!(invalid_argument("resize(2, bicubic)"), ("resize".to_string(), "bicubic".to_string()));
+        assert_eq!(invalid_argument("colour_key(#ffcc0)"), ("colour_key".to_string(), "#ffcc0".to_string()));
+        assert_eq!(invalid_argument("colour_key(ffcc00)"), ("colour_key".to_string(), "ffcc00".to_string()));
+        assert_eq!(invalid_argument("dilate(-1)"), ("dilate".to_string(), "-1".to_string()));
+    }
+
+    #[test]
+    fn otsu_splits_two_tones_at_the_darker_one() {
+        let image = row(&[40, 40, 40, 200, 200, 200, 200, 200]);
+        assert_eq!(otsu_level(&image), 41);
+        assert_eq!(values(&apply_step(PreprocessingStep::Otsu, image)), vec![0, 0, 0, 255, 255, 255, 255, 255]);
+        assert_eq!(otsu_level(&row(&[90; 4])), 1); //A single tone has nothing to split
+    }
+
+    #[test]
+    fn adaptive_threshold_follows_uneven_background() {
+        //Text on a dark background on the left and on a light background on the right, which no global level separates
+        let image = row(&[60, 60, 20, 60, 60, 60, 180, 180, 140, 180, 180]);
+        let thresholded = values(&adaptive_threshold(&image, 1, 10));
+        assert_eq!(thresholded[2], 0);
+        assert_eq!(thresholded[8], 0);
+        for x in [0, 1, 3, 7, 9, 10] {
+            assert_eq!(thresholded[x], 255, "pixel {}", x);
+        }
+    }
+
+    #[test]
+    fn contrast_stretch_maps_percentiles_to_full_range() {
+        let mut image = GrayImage::from_fn(10, 10, |x, y| Luma([50 + (y * 10 + x) as u8]));
+        contrast_stretch(&mut image, 10.0, 90.0);
+        assert_eq!(image.get_pixel(0, 0).0[0], 0); //50, below the 10th percentile
+        assert_eq!(image.get_pixel(9, 0).0[0], 0); //59, the 10th percentile
+        assert_eq!(image.get_pixel(9, 4).0[0], 127); //99, half way
+        assert_eq!(image.get_pixel(9, 8).0[0], 255); //139, the 90th percentile
+        assert_eq!(image.get_pixel(9, 9).0[0], 255); //149
+
+        let mut flat = row(&[70; 5]);
+        contrast_stretch(&mut flat, 1.0, 99.0);
+        assert_eq!(values(&flat), vec![70; 5]);
+    }
+
+    #[test]
+    fn colour_key_keeps_pixels_within_tolerance() {
+        let colours = [[255, 204, 0], [230, 190, 30], [230, 190, 31], [255, 255, 255], [0, 0, 0]];
+        let image = RgbImage::from_fn(colours.len() as u32, 1, |x, _y| Rgb(colours[x as usize]));
+        let keyed = colour_key(&DynamicImage::ImageRgb8(image), [255, 204, 0], 30);
+        assert_eq!(values(&keyed), vec![0, 0, 255, 255, 255]);
+    }
+
+    #[test]
+    fn morphology_grows_and_shrinks_dark_pixels() {
+        let dot = dots(5, &[(2, 2)]);
+        let square = (1..=3).flat_map(|y| (1..=3).map(move |x| (x, y))).collect::<Vec<_>>();
+        let dilated = apply_step(PreprocessingStep::Dilate { radius: 1 }, dot.clone());
+        assert_eq!(black_pixels(&dilated), square);
+        assert_eq!(black_pixels(&apply_step(PreprocessingStep::Erode { radius: 1 }, dilated)), vec![(2, 2)]);
+        assert!(black_pixels(&apply_step(PreprocessingStep::Erode { radius: 1 }, dot.clone())).is_empty());
+        assert!(black_pixels(&apply_step(PreprocessingStep::Open { radius: 1 }, dot.clone())).is_empty());
+        assert_eq!(black_pixels(&apply_step(PreprocessingStep::Close { radius: 1 }, dot)), vec![(2, 2)]);
+
+        //Dilating at the edge stays within the image
+        let corner = apply_step(PreprocessingStep::Dilate { radius: 2 }, dots(4, &[(0, 0)]));
+        assert_eq!(black_pixels(&corner).len(), 9);
+    }
+
+    #[test]
+    fn detects_light_text_on_dark_background() {
+        assert_eq!(detect_polarity(&page(20, 230)), Polarity::LightOnDark);
+        assert_eq!(detect_polarity(&page(240, 30)), Polarity::DarkOnLight);
+        //Mostly text, but the border still shows the background
+        let crowded = DynamicImage::ImageLuma8(GrayImage::from_fn(40, 20, |x, y| match x < 2 || y < 2 || x >= 38 || y >= 18 {
+            true => Luma([10]),
+            false => Luma([200]),
+        }));
+        assert_eq!(detect_polarity(&crowded), Polarity::LightOnDark);
+    }
+
+    #[test]
+    fn inverts_dark_backgrounds_before_other_steps() {
+        let mut stages = Vec::new();
+        let (image, polarity) = PreprocessingPipeline::new(vec![PreprocessingStep::Threshold { level: 128 }])
+            .apply_with_stages(&page(20, 230), |name, _image| stages.push(name.to_string()));
+        assert_eq!(polarity, Polarity::LightOnDark);
+        assert_eq!(stages, vec!["auto_invert", "threshold"]);
+        assert_eq!(image.get_pixel(0, 0).0[0], 255);
+        assert_eq!(image.get_pixel(20, 10).0[0], 0);
+    }
+
+    #[test]
+    fn leaves_light_backgrounds_alone() {
+        let mut stages = Vec::new();
+        let (image, polarity) = PreprocessingPipeline::new(Vec::new())
+            .apply_with_stages(&page(240, 30), |name, _image| stages.push(name.to_string()));
+        assert_eq!(polarity, Polarity::DarkOnLight);
+        assert!(stages.is_empty());
+        assert_eq!(image, page(240, 30).into_luma8());
+    }
+
+    #[test]
+    fn skips_auto_invert_when_disabled_or_handled_by_steps() {
+        let (image, polarity) = PreprocessingPipeline::new(Vec::new()).with_auto_invert(false).apply(&page(20, 230));
+        assert_eq!(polarity, Polarity::LightOnDark);
+        assert_eq!(image, page(20, 230).into_luma8());
+        let (image, _) = PreprocessingPipeline::new(vec![PreprocessingStep::Invert]).apply(&page(20, 230));
+        assert_eq!(image.get_pixel(0, 0).0[0], 235); //Inverted once by the step, not twice
+    }
+}
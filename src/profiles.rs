@@ -0,0 +1,223 @@
+use std::{ops::Bound, path::PathBuf};
+
+use serde::{Serialize, Deserialize};
+use winit::event::VirtualKeyCode;
+
+use crate::{config::{Config, ConfigError, ScreenConfig}, positioning_structs::OverlayLayout, supported_languages::SupportedLanguages};
+
+/// Settings for a single game, stored in the config as a `[profile.<name>]` table.
+/// Anything left out is taken from the shared settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x_pos: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y_pos: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<SupportedLanguages>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<OverlayLayout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preprocessing_profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_invert: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_dictionary: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hotkey: Option<String>,
+}
+
+impl Profile {
+    /// The profile's capture rectangle, once the overlay has been used with it.
+    pub fn get_screen(&self) -> Option<ScreenConfig> {
+        match (self.x_pos, self.y_pos, self.width, self.height) {
+            (Some(x_pos), Some(y_pos), Some(width), Some(height)) => Some(ScreenConfig { x_pos, y_pos, width, height }),
+            _ => None,
+        }
+    }
+
+    pub fn set_screen(&mut self, screen: ScreenConfig) {
+        self.x_pos = Some(screen.x_pos);
+        self.y_pos = Some(screen.y_pos);
+        self.width = Some(screen.width);
+        self.height = Some(screen.height);
+    }
+}
+
+/// The shared settings with the named profile's overrides applied.
+pub fn apply(config: &Config, profile: Option<&str>) -> Config {
+    let mut effective = config.clone();
+    let profile = match profile.and_then(|profile| config.profile.get(profile)) {
+        Some(profile) => profile,
+        None => return effective,
+    };
+    if let Some(screen) = profile.get_screen() {
+        effective.screen = screen;
+    }
+    if let Some(language) = profile.language {
+        effective.other.language = language;
+    }
+    if let Some(layout) = profile.layout {
+        effective.other.layout = layout;
+    }
+    if let Some(preprocessing_profile) = &profile.preprocessing_profile {
+        effective.other.preprocessing_profile = preprocessing_profile.clone();
+    }
+    if let Some(auto_invert) = profile.auto_invert {
+        effective.other.auto_invert = auto_invert;
+    }
+    if let Some(user_dictionary) = &profile.user_dictionary {
+        effective.dictionary.user_dictionary = user_dictionary.clone();
+    }
+    return effective;
+}
+
+/// Check that the named profile exists, returning its name as stored in the config.
+pub fn find(config: &Config, name: &str) -> Result<String, ConfigError> {
+    if config.profile.contains_key(name) {
+        return Ok(name.to_string());
+    }
+    return Err(ConfigError::UnknownProfile {
+        name: name.to_string(),
+        available: match config.profile.is_empty() {
+            true => "none are defined".to_string(),
+            false => config.profile.keys().cloned().collect::<Vec<_>>().join(", "),
+        },
+    });
+}
+
+/// The profile after the given one in alphabetical order, wrapping around to the first.
+pub fn next(config: &Config, profile: Option<&str>) -> Option<String> {
+    let mut profiles = config.profile.keys();
+    match profile {
+        Some(profile) => config.profile.range::<str, _>((Bound::Excluded(profile), Bound::Unbounded))
+            .next()
+            .map(|(name, _)| name)
+            .or_else(|| profiles.next())
+            .cloned(),
+        None => profiles.next().cloned(),
+    }
+}
+
+/// The profile whose `hotkey` is the given key.
+pub fn with_hotkey(config: &Config, keycode: VirtualKeyCode) -> Option<String> {
+    config.profile.iter()
+        .find(|(_, profile)| profile.hotkey.as_deref().and_then(parse_hotkey) == Some(keycode))
+        .map(|(name, _)| name.clone())
+}
+
+/// Profile hotkeys are function keys, so they can't clash with typing in the line editor.
+pub fn parse_hotkey(name: &str) -> Option<VirtualKeyCode> {
+    let keycode = match name.to_uppercase().as_str() {
+        "F1" => VirtualKeyCode::F1,
+        "F4" => VirtualKeyCode::F4,
+        "F5" => VirtualKeyCode::F5,
+        "F6" => VirtualKeyCode::F6,
+        "F7" => VirtualKeyCode::F7,
+        "F8" => VirtualKeyCode::F8,
+        "F9" => VirtualKeyCode::F9,
+        "F10" => VirtualKeyCode::F10,
+        "F11" => VirtualKeyCode::F11,
+        "F12" => VirtualKeyCode::F12,
+        _ => return None, //F2 and F3 are taken by editing and cycling through profiles
+    };
+    return Some(keycode);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn rejects_reserved_hotkeys() {
+        assert_eq!(parse_hotkey("F2"), None);
+        assert_eq!(parse_hotkey("F3"), None);
+        assert_eq!(parse_hotkey("f3"), None);
+        assert_eq!(parse_hotkey("F13"), None);
+        assert_eq!(parse_hotkey("A"), None);
+        assert_eq!(parse_hotkey(""), None);
+    }
+
+    #[test]
+    fn parses_other_function_keys() {
+        let keys = [
+            ("F1", VirtualKeyCode::F1), ("F4", VirtualKeyCode::F4), ("F5", VirtualKeyCode::F5), ("F6", VirtualKeyCode::F6),
+            ("F7", VirtualKeyCode::F7), ("F8", VirtualKeyCode::F8), ("F9", VirtualKeyCode::F9), ("F10", VirtualKeyCode::F10),
+            ("F11", VirtualKeyCode::F11), ("F12", VirtualKeyCode::F12),
+        ];
+        for (name, keycode) in keys {
+            assert_eq!(parse_hotkey(name), Some(keycode), "{}", name);
+        }
+        assert_eq!(parse_hotkey("f5"), Some(VirtualKeyCode::F5));
+        let config = parse("[profile.Persona5]\nhotkey = \"f5\"\n[profile.Pokemon]\nhotkey = \"F6\"");
+        assert_eq!(with_hotkey(&config, VirtualKeyCode::F6).as_deref(), Some("Pokemon"));
+        assert_eq!(with_hotkey(&config, VirtualKeyCode::F7), None);
+    }
+
+    #[test]
+    fn apply_overrides_only_the_fields_set() {
+        let config = parse("\
+[screen]
+x_pos = 1
+y_pos = 2
+width = 300
+height = 40
+
+[other]
+language = \"ChiSim\"
+layout = \"Outline\"
+
+[profile.Persona5]
+language = \"Jpn\"
+auto_invert = false
+
+[profile.Placed]
+x_pos = 10
+y_pos = 20
+width = 640
+height = 120
+user_dictionary = \"placed.txt\"
+");
+        let persona = apply(&config, Some("Persona5"));
+        assert_eq!(persona.other.language, SupportedLanguages::Jpn);
+        assert!(!persona.other.auto_invert);
+        assert_eq!(persona.other.layout, OverlayLayout::Outline);
+        assert_eq!(persona.other.preprocessing_profile, config.other.preprocessing_profile);
+        assert_eq!(persona.screen, config.screen);
+        assert_eq!(persona.dictionary.user_dictionary, config.dictionary.user_dictionary);
+
+        let placed = apply(&config, Some("Placed"));
+        assert_eq!(placed.screen, ScreenConfig { x_pos: 10, y_pos: 20, width: 640, height: 120 });
+        assert_eq!(placed.dictionary.user_dictionary, PathBuf::from("placed.txt"));
+        assert_eq!(placed.other.language, SupportedLanguages::ChiSim);
+        assert!(placed.other.auto_invert);
+
+        //A partly placed window keeps the shared rectangle
+        let partial = apply(&parse("[profile.Partial]\nwidth = 640"), Some("Partial"));
+        assert_eq!(partial.screen, ScreenConfig::default());
+
+        assert_eq!(apply(&config, Some("Missing")).other.language, SupportedLanguages::ChiSim);
+        assert_eq!(apply(&config, None).screen, config.screen);
+    }
+
+    #[test]
+    fn next_wraps_around_in_name_order() {
+        let config = parse("[profile.Pokemon]\n[profile.Persona5]\n[profile.Yakuza]");
+        assert_eq!(next(&config, None).as_deref(), Some("Persona5"));
+        assert_eq!(next(&config, Some("Persona5")).as_deref(), Some("Pokemon"));
+        assert_eq!(next(&config, Some("Pokemon")).as_deref(), Some("Yakuza"));
+        assert_eq!(next(&config, Some("Yakuza")).as_deref(), Some("Persona5"));
+        assert_eq!(next(&config, Some("Removed")).as_deref(), Some("Yakuza")); //Carries on from where it would have been
+        assert_eq!(next(&Config::default(), None), None);
+        assert_eq!(next(&Config::default(), Some("Persona5")), None);
+    }
+}
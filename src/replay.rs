@@ -0,0 +1,85 @@
+use std::path::{Path, PathBuf};
+
+use glyph_brush::{GlyphCalculator, GlyphCalculatorBuilder};
+use serde::Serialize;
+
+use crate::{capture::{CaptureArea, CaptureError, CaptureSource, ReplaySource}, engine::TesseractEngine, fonts, ocr, positioning_structs::{OverlayLayout, PresentableLine, PresentableWord}, preprocessing::{Polarity, PreprocessingPipeline}, supported_languages::SupportedLanguages};
+
+#[derive(Debug, Serialize)]
+struct FrameLayout {
+    frame: PathBuf,
+    detected_polarity: Polarity,
+    lines: Vec<LineLayout>,
+}
+
+#[derive(Debug, Serialize)]
+struct LineLayout {
+    min: [f32; 2],
+    max: [f32; 2],
+    scale: f32,
+    vertical: bool,
+    words: Vec<WordLayout>,
+}
+
+#[derive(Debug, Serialize)]
+struct WordLayout {
+    text: String,
+    position: [f32; 2],
+    max: [f32; 2],
+    confidence: f32,
+}
+
+impl From<&PresentableLine> for LineLayout {
+    fn from(line: &PresentableLine) -> Self {
+        Self {
+            min: [line.get_min().get_x(), line.get_min().get_y()],
+            max: [line.get_max().get_x(), line.get_max().get_y()],
+            scale: line.get_scale().y,
+            vertical: line.is_vertical(),
+            words: line.get_words().iter().map(WordLayout::from).collect(),
+        }
+    }
+}
+
+impl From<&PresentableWord> for WordLayout {
+    fn from(word: &PresentableWord) -> Self {
+        Self {
+            text: word.get_text().clone(),
+            position: [word.get_min().get_x(), word.get_min().get_y()],
+            max: [word.get_max().get_x(), word.get_max().get_y()],
+            confidence: word.get_confidence(),
+        }
+    }
+}
+
+/// Feed saved captures through the same OCR and layout code as the overlay, without a window or GPU,
+/// printing the overlay layout of each frame as one JSON object.
+pub fn run_replay(path: PathBuf, layout: OverlayLayout, language: SupportedLanguages, pipeline: &PreprocessingPipeline, debug_dump_dir: Option<&Path>) -> Result<(), CaptureError> {
+    let mut source = ReplaySource::new(path)?;
+    let mut engine = TesseractEngine::new(language);
+    let glyph_calculator: GlyphCalculator = GlyphCalculatorBuilder::using_font(fonts::load_font()).build();
+    let frames = source.get_frames().clone();
+    for frame in frames {
+        let image = source.capture(CaptureArea::default())?;
+        match ocr::ocr_image_with_engine(&image, &mut engine, pipeline, debug_dump_dir) {
+            Ok(result) => {
+                let mut glyph_cruncher = glyph_calculator.cache_scope();
+                let detected_polarity = result.get_detected_polarity();
+                let lines = result.into_lines().into_iter()
+                    .map(|line| {
+                        let is_vertical = line.is_vertical();
+                        PresentableLine::from_hocr_with_layout(line.into_words(), is_vertical, layout, &mut glyph_cruncher)
+                    })
+                    .collect::<Vec<_>>();
+                let layout = FrameLayout {
+                    frame,
+                    detected_polarity,
+                    lines: lines.iter().map(LineLayout::from).collect(),
+                };
+                println!("{}", serde_json::to_string(&layout).unwrap());
+            },
+            Err(e) => eprintln!("{}: {}", frame.display(), e),
+        }
+    }
+    return Ok(());
+}
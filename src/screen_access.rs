@@ -1,20 +1,24 @@
-use std::{mem, time::{Instant, Duration}};
+use std::{mem, path::{Path, PathBuf}, sync::Arc, time::{Instant, Duration}};
 
 use abort_on_drop::ChildTask;
 use bytemuck::{Pod, Zeroable};
-use chinese_dictionary::tokenize;
-use configparser::ini::Ini;
-use html_parser::Node;
+use clipboard::{ClipboardContext, ClipboardProvider};
+use image::DynamicImage;
 use tokio::sync::{watch, mpsc};
 use wgpu::{BufferUsages, SurfaceConfiguration};
-use wgpu_glyph::{GlyphBrush, ab_glyph, GlyphBrushBuilder, OwnedSection};
+use wgpu_glyph::{GlyphBrush, GlyphCruncher, ab_glyph::Rect, GlyphBrushBuilder, OwnedSection, OwnedText, Section};
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::{WindowBuilder, Window}, dpi::{PhysicalSize, PhysicalPosition, Size},
+    window::{WindowBuilder, Window}, dpi::{PhysicalSize, PhysicalPosition},
 };
 
-use crate::{ocr, positioning_structs::{PresentableLine, PixelPoint, HocrWord}, supported_languages::SupportedLanguages};
+use crate::{capture::{self, CaptureArea, CaptureSource, ScreenSource}, engine::{SupportedEngines, TesseractEngine}, change_detection::{StabilitySettings, WatchSettings}, config::{Config, ConfigError, ScreenConfig}, corrections::CorrectionLog, dictionary::{self, DictionaryFile}, fonts, ocr::{self, OcrWorkerSettings, WorkerMessage}, positioning_structs::{OcrSymbol, OverlayLayout, PresentableLine, PixelPoint}, profiles::{self, Profile}, supported_languages::{Script, SupportedLanguages}};
+
+/// Pixels of the capture kept around a corrected line when its crop is saved.
+const CORRECTION_CROP_PADDING: f32 = 4.0;
+/// How many quads the vertex and index buffers have room for before they are grown.
+const INITIAL_QUAD_CAPACITY: u64 = 2500;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
@@ -60,6 +64,23 @@ impl WindowState {
     }
 }
 
+/// Text being typed over a scanned line, which replaces the line when saved.
+#[derive(Debug)]
+struct LineEditor {
+    line_index: usize,
+    text: String,
+    preedit: String, //Uncommitted text from an input method
+    is_ime_active: bool,
+}
+
+/// A low confidence symbol whose alternatives are shown in the popup, waiting for one to be picked.
+#[derive(Debug, Clone, Copy)]
+struct PendingCorrection {
+    line_index: usize,
+    word_index: usize,
+    symbol_index: usize,
+}
+
 struct State {
     main_window_state: WindowState,
     popup_window_state: WindowState,
@@ -73,16 +94,25 @@ struct State {
     glyph_brush: GlyphBrush<()>,
     _ocr_thread: ChildTask<()>,
     ocr_job_timer: Option<Instant>,
-    ocr_send_channel: watch::Sender<(i32, i32, u32, u32)>,
-    ocr_receive_channel: mpsc::Receiver<String>,
+    ocr_send_channel: watch::Sender<CaptureArea>,
+    ocr_receive_channel: mpsc::Receiver<WorkerMessage>,
     ocr_text: Option<Vec<PresentableLine>>,
-    config_parser: Ini,
+    cursor_position: PixelPoint,
+    pending_correction: Option<PendingCorrection>,
+    editor: Option<LineEditor>,
+    modifiers: ModifiersState,
+    last_capture: Option<Arc<DynamicImage>>,
+    correction_log: Option<CorrectionLog>,
+    config: Config,
+    config_path: PathBuf,
+    profile: Option<String>,
+    layout: OverlayLayout,
     language: SupportedLanguages,
 }
 
 impl State {
     // Creating some of the wgpu types requires async code
-    async fn new(main_window: Window, popup_window: Window, mut config_parser: Ini) -> Self {
+    async fn new(main_window: Window, popup_window: Window, config: Config, config_path: PathBuf, profile: Option<String>) -> Self {
         // The instance is a handle to our GPU
         // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -99,11 +129,10 @@ impl State {
         
         let adapter = instance
         .enumerate_adapters(wgpu::Backends::all())
-        .filter(|adapter| {
+        .find(|adapter| {
             // Check if this adapter supports our surface
             adapter.is_surface_supported(&main_window_surface)
         })
-        .next()
         .unwrap();
 
         let (device, queue) = adapter.request_device(
@@ -128,8 +157,7 @@ impl State {
         // sRGB surfaces, you'll need to account for that when drawing to the frame.
         let surface_format = surface_caps.formats.iter()
             .copied()
-            .filter(|f| f.describe().srgb)
-            .next()
+            .find(|f| f.describe().srgb)
             .unwrap_or(surface_caps.formats[0]);
 
         let main_window_state = configure_main_window(main_window, surface_format, &surface_caps, main_window_surface, &device);
@@ -183,39 +211,18 @@ impl State {
             multiview: None,
         });
 
-        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Vertex Buffer"),
-            size: 10000 * mem::size_of::<Vertex>() as u64, //Assuming we never need more than 1000 vertices
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Index Buffer"),
-            size: 10000 * mem::size_of::<u16>() as u64,
-            usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        //Each quad is 4 vertices and 6 indices, and the buffers grow in render if a busy screen needs more
+        let vertex_buffer = create_geometry_buffer(&device, "Vertex Buffer", BufferUsages::VERTEX, INITIAL_QUAD_CAPACITY * 4 * mem::size_of::<Vertex>() as u64);
+        let index_buffer = create_geometry_buffer(&device, "Index Buffer", BufferUsages::INDEX, INITIAL_QUAD_CAPACITY * 6 * mem::size_of::<u32>() as u64);
 
         // Prepare glyph_brush
-        let simhei = ab_glyph::FontArc::try_from_slice(include_bytes!(
-            "SimHei.ttf"
-        )).unwrap();
+        let font = fonts::load_font();
 
-        let glyph_brush = GlyphBrushBuilder::using_font(simhei.clone())
+        let glyph_brush = GlyphBrushBuilder::using_font(font)
             .build(&device, surface_format);
 
-        let (main_thread_send_channel, worker_thread_receive_channel) = watch::channel((0, 0, 0, 0));
-        let (worker_thread_send_channel, main_thread_receive_channel) = mpsc::channel(1);
-
-        let language = serde_json::from_str::<SupportedLanguages>(
-            &config_parser.get("other", "language").or(Some("\"ChiTra\"".to_string())).unwrap()
-        ).expect("Expected language ChiTra or ChiSim");
-        config_parser.set("other", "language", Some(serde_json::to_string(&language).unwrap()));
-        
-        let _ocr_thread = ChildTask::from(tokio::task::spawn_blocking(move || {
-            ocr::build_ocr_worker(worker_thread_receive_channel, worker_thread_send_channel, language);
-        }));
+        let overlay_config = read_config(&config, profile.as_deref());
+        let (_ocr_thread, ocr_send_channel, ocr_receive_channel) = spawn_ocr_worker(overlay_config.engine, overlay_config.language, overlay_config.capture_source, overlay_config.worker_settings);
 
         Self {
             main_window_state,
@@ -229,30 +236,142 @@ impl State {
             glyph_brush,
             _ocr_thread,
             ocr_job_timer: None,
-            ocr_send_channel: main_thread_send_channel,
-            ocr_receive_channel: main_thread_receive_channel,
+            ocr_send_channel,
+            ocr_receive_channel,
             ocr_text: None,
+            cursor_position: PixelPoint::new(0.0, 0.0),
+            pending_correction: None,
+            editor: None,
+            modifiers: ModifiersState::default(),
+            last_capture: None,
+            correction_log: overlay_config.correction_log,
             popup_text: None,
-            config_parser,
-            language
+            config,
+            config_path,
+            profile,
+            layout: overlay_config.layout,
+            language: overlay_config.language,
         }
     }
 
-    fn input(&mut self, _event: &WindowEvent) -> bool {
-        false
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                false
+            },
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F2),
+                        ..
+                    },
+                ..
+            } if self.editor.is_none() => self.start_editing(),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F3),
+                        ..
+                    },
+                ..
+            } if self.editor.is_none() => {
+                match profiles::next(&self.config, self.profile.as_deref()) {
+                    Some(profile) => self.switch_profile(profile),
+                    None => log::info!("No profiles are defined in {}", self.config_path.display()),
+                }
+                true
+            },
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } if self.editor.is_none() && profiles::with_hotkey(&self.config, *keycode).is_some() => {
+                self.switch_profile(profiles::with_hotkey(&self.config, *keycode).unwrap());
+                true
+            },
+            _ => self.handle_editor_input(event),
+        }
     }
 
     fn schedule_ocr_job(&mut self) {
         if self.ocr_text.is_some() {
             self.ocr_text = None;
+            self.stop_editing(false);
+            self.pending_correction = None;
         }
         self.ocr_job_timer = Instant::now().checked_add(Duration::from_millis(200));
     }
 
     fn check_running_job(&mut self) {
-        if let Ok(ocr_text) = self.ocr_receive_channel.try_recv() {
-            self.ocr_text = Some(self.nodes_to_lines(&html_parser::Dom::parse(&ocr_text).unwrap().children));
-            self.render_main_window().unwrap();
+        match self.ocr_receive_channel.try_recv() {
+            Ok(WorkerMessage::Result(ocr_result)) => {
+                if self.editor.take().is_some() { //The line being edited has gone
+                    self.main_window_state.window.set_ime_allowed(false);
+                    self.hide_popup();
+                }
+                self.pending_correction = None;
+                self.last_capture = Some(ocr_result.get_capture().clone());
+                self.main_window_state.window.set_title(&match &self.profile {
+                    Some(profile) => format!("decky_translate [{}] (detected {})", profile, ocr_result.get_detected_polarity()),
+                    None => format!("decky_translate (detected {})", ocr_result.get_detected_polarity()),
+                });
+                self.ocr_text = Some(ocr_result.into_lines().into_iter()
+                    .map(|line| {
+                        let is_vertical = line.is_vertical();
+                        PresentableLine::from_hocr_with_layout(line.into_words(), is_vertical, self.layout, &mut self.glyph_brush)
+                    })
+                    .collect());
+                self.draw_main_window();
+            },
+            Ok(WorkerMessage::TextChanged) if self.editor.is_some() => {}, //Don't throw away a line which is being corrected
+            Ok(WorkerMessage::TextChanged) => {
+                //Redrawing clears the old text and schedules a new scan, so the overlay isn't captured along with the game
+                self.main_window_state.window.request_redraw();
+            },
+            Err(_) => {},
+        }
+    }
+
+    /// Switch to the named profile, restarting the OCR worker with its settings and moving the overlay over its
+    /// capture rectangle.
+    fn switch_profile(&mut self, profile: String) {
+        if self.profile.as_ref() == Some(&profile) {
+            return;
+        }
+        log::info!("Switching to profile {}", profile);
+        self.stop_editing(false);
+        self.pending_correction = None;
+        self.hide_popup();
+        let overlay_config = read_config(&self.config, Some(&profile));
+        //Replacing the channels stops the old worker
+        (self._ocr_thread, self.ocr_send_channel, self.ocr_receive_channel) = spawn_ocr_worker(overlay_config.engine, overlay_config.language, overlay_config.capture_source, overlay_config.worker_settings);
+        self.language = overlay_config.language;
+        self.layout = overlay_config.layout;
+        self.correction_log = overlay_config.correction_log;
+        self.config.profiles.active = Some(profile.clone());
+        if let Some(screen) = self.config.profile.get(&profile).and_then(Profile::get_screen) {
+            self.main_window_state.window.set_outer_position(PhysicalPosition::new(screen.x_pos, screen.y_pos));
+            self.main_window_state.resize(&self.device, PhysicalSize::new(screen.width, screen.height));
+        }
+        self.profile = Some(profile);
+        self.main_window_state.window.request_redraw(); //Scan again with the new settings
+    }
+
+    /// Draw the main window straight away, such as when a scan finishes. A redraw request can't be used for this, since
+    /// it clears the text and schedules a new scan. Frames which fail to draw are skipped, as in the event loop.
+    fn draw_main_window(&mut self) {
+        match self.render_main_window() {
+            Ok(_) => {},
+            // Reconfigure the surface if lost
+            Err(wgpu::SurfaceError::Lost) => self.main_window_state.resize(&self.device, self.main_window_state.size),
+            Err(e) => log::warn!("Skipped drawing the overlay: {:?}", e),
         }
     }
 
@@ -285,8 +404,8 @@ impl State {
             render_pass.set_pipeline(&self.render_pipeline);
 
             if let Some(lines) = &self.ocr_text {
-                let mut vertices: Vec<Vertex> = Vec::with_capacity(10000 * mem::size_of::<Vertex>());
-                let mut indices: Vec<u32> = Vec::with_capacity(10000 * mem::size_of::<u32>());
+                let mut vertices: Vec<Vertex> = Vec::new();
+                let mut indices: Vec<u32> = Vec::new();
                 let mut offset = 0;
                 let mut num_indices = 0;
                 let screen_size = PixelPoint::new(self.main_window_state.config.width as f32, self.main_window_state.config.height as f32);
@@ -297,8 +416,16 @@ impl State {
                     num_indices += line_indices.len() as u32;
                     indices.append(&mut line_indices);
                 }
-                self.queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
-                self.queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&indices));
+                let vertex_bytes: &[u8] = bytemuck::cast_slice(&vertices);
+                let index_bytes: &[u8] = bytemuck::cast_slice(&indices);
+                if vertex_bytes.len() as u64 > self.vertex_buffer.size() {
+                    self.vertex_buffer = create_geometry_buffer(&self.device, "Vertex Buffer", BufferUsages::VERTEX, (vertex_bytes.len() as u64).next_power_of_two());
+                }
+                if index_bytes.len() as u64 > self.index_buffer.size() {
+                    self.index_buffer = create_geometry_buffer(&self.device, "Index Buffer", BufferUsages::INDEX, (index_bytes.len() as u64).next_power_of_two());
+                }
+                self.queue.write_buffer(&self.vertex_buffer, 0, vertex_bytes);
+                self.queue.write_buffer(&self.index_buffer, 0, index_bytes);
 
                 render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
                 render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
@@ -308,8 +435,9 @@ impl State {
 
         if let Some(lines) = &self.ocr_text {
             for line in lines {
-                let section = line.get_section();
-                self.glyph_brush.queue(section);
+                for section in line.get_sections() {
+                    self.glyph_brush.queue(section);
+                }
             }
             self.glyph_brush.draw_queued(&self.device, &mut self.staging_belt, &mut encoder, &view, self.main_window_state.size.width, self.main_window_state.size.height).unwrap();
         }
@@ -365,105 +493,343 @@ impl State {
     }
 
     fn handle_cursor(&mut self, cursor_position: &PixelPoint) {
+        self.cursor_position = *cursor_position;
         if let Some(bbox_lines) = &mut self.ocr_text {
             for line in bbox_lines {
                 line.handle_cursor(cursor_position);
             }
-            self.render_main_window().unwrap();
+            self.draw_main_window();
         }
     }
 
     fn handle_click(&mut self) {
-        let mut something_clicked = false;
+        let mut popup = None;
+        self.pending_correction = None;
         if let Some(lines) = &self.ocr_text {
-            for line in lines {
-                for word in line.get_words() {
+            for (line_index, line) in lines.iter().enumerate() {
+                for (word_index, word) in line.get_words().iter().enumerate() {
                     if word.is_highlighted() {
-                        let (text_section, bounds) = word.generate_translation_section(&mut self.glyph_brush, &self.language);
+                        //Clicking a red or orange character offers the engine's alternatives for it instead of translating the word
+                        let symbols = word.get_symbols();
+                        let is_doubtful = |symbol: &OcrSymbol| symbol.is_uncertain() || symbol.is_dictionary_corrected();
+                        let uncertain_symbol = word.symbol_at(&self.cursor_position)
+                            .filter(|index| is_doubtful(&symbols[*index]))
+                            .or_else(|| symbols.iter().enumerate()
+                                .filter(|(_, symbol)| is_doubtful(symbol))
+                                .min_by(|lhs, rhs| lhs.1.get_confidence().total_cmp(&rhs.1.get_confidence()))
+                                .map(|(index, _)| index))
+                            .filter(|index| !symbols[*index].get_choices().is_empty());
+                        let (text_section, bounds) = match uncertain_symbol {
+                            Some(symbol_index) => {
+                                self.pending_correction = Some(PendingCorrection {
+                                    line_index,
+                                    word_index,
+                                    symbol_index,
+                                });
+                                word.generate_choices_section(symbol_index, &mut self.glyph_brush)
+                            },
+                            None => word.generate_translation_section(&mut self.glyph_brush, &self.language),
+                        };
                         if let Some(bounds) = bounds {
-                            self.popup_text = Some(text_section);
-                            let new_size = PhysicalSize { 
-                                width: (bounds.max.x - bounds.min.x) as u32, 
-                                height: (bounds.max.y - bounds.min.y) as u32 
-                            };
-                            self.popup_window_state.resize(&self.device, new_size);
-                            self.popup_window_state.set_visible(true);
-                            let main_window_position = self.main_window_state.window.inner_position().unwrap();
-                            let popup_new_position = PhysicalPosition {
-                                x: main_window_position.x as u32 + word.get_min().get_x() as u32 - (new_size.width / 2) + (line.get_scale().x as u32 / 2),
-                                y: main_window_position.y as u32 + word.get_min().get_y() as u32 - new_size.height - 10,
-                            };
-                            self.popup_window_state.window.set_outer_position(popup_new_position);
-                            self.popup_window_state.window.set_window_level(winit::window::WindowLevel::AlwaysOnTop);
-                            self.popup_window_state.window.request_redraw();
-                            something_clicked = true;
+                            let anchor = PixelPoint::new((word.get_min().get_x() + word.get_max().get_x()) / 2.0, word.get_min().get_y());
+                            popup = Some((text_section, bounds, anchor));
                         }
                     }
                 }
             }
         }
-        if !something_clicked {
-            self.popup_text = None;
-            self.popup_window_state.set_visible(false);
-            self.popup_window_state.window.request_redraw();
+        match popup {
+            Some((text_section, bounds, anchor)) => self.show_popup(text_section, bounds, anchor),
+            None => self.hide_popup(),
         }
     }
 
-    fn nodes_to_lines(&mut self, nodes: &Vec<Node>) -> Vec<PresentableLine> {
-        let mut lines: Vec<PresentableLine> = Vec::new();
-        for node in nodes {
-            if let html_parser::Node::Element(element) = node {
-                if element.classes.contains(&"ocr_line".to_string()) { // is individual line
-                    let num_words = element.children.len();
-                    let mut words = Vec::with_capacity(num_words);
-                    for word in &element.children {
-                        if let html_parser::Node::Element(word_element) = word {
-                            let title = word_element.attributes["title"].clone().unwrap();
-                            let mut parts = title.split(" ");
-                            parts.next();
-                            let x = parse_bbox_f32(parts.next().unwrap()); //Bounds reported by tesseract, unfortunately they're very inaccurate
-                            let y = parse_bbox_f32(parts.next().unwrap()); //The sizes are accurate but the positions aren't
-                            let x2 = parse_bbox_f32(parts.next().unwrap());
-                            let y2 = parse_bbox_f32(parts.next().unwrap());
-                            parts.next();
-                            let confidence = parts.next().unwrap().parse::<f32>().unwrap();
-                            let text = get_text_child(&word_element.children);
-                            let word = HocrWord::new(
-                                text,
-                                PixelPoint::new(x, y),
-                                PixelPoint::new(x2, y2),
-                                confidence,
-                            );
-                            words.push(word);
-                        }
-                    }
-                    let raw_text: String = words.iter().map(|hocr_word| hocr_word.get_text().to_string()).collect();
-                    let tokenized_text = tokenize(&raw_text);
-                    let mut tokenized_words = Vec::with_capacity(tokenized_text.len());
-                    let mut i = 0;
-                    for token in tokenized_text {
-                        let first_char = token.as_bytes()[0];
-                        if let Some((index, _word)) = words.iter().map(|hocr_word| hocr_word.get_text()).enumerate().skip(i).find(|(_i, word)| word.as_bytes()[0] == first_char) {
-                            for y in i .. index {
-                                tokenized_words.push(words[y].clone());
-                            }
-                            i = index;
-                            let len = token.chars().count();
-                            tokenized_words.push(words[i+1 .. i+len].iter().fold(words[i].clone(), |lhs, rhs| lhs + rhs));
-                            i += len;
-                        }
-                    }if !tokenized_words.is_empty() {
-                        let line = PresentableLine::from_hocr(tokenized_words, &mut self.glyph_brush);
-                        lines.push(line);
-                    }
-                } else { // call recursively until we reach individual words
-                    lines.append(&mut self.nodes_to_lines(&node.element().unwrap().children));
-                }
+    /// Show the popup window centred above the given point in the main window.
+    fn show_popup(&mut self, text_section: OwnedSection, bounds: Rect, anchor: PixelPoint) {
+        self.popup_text = Some(text_section);
+        let new_size = PhysicalSize { 
+            width: (bounds.max.x - bounds.min.x) as u32, 
+            height: (bounds.max.y - bounds.min.y) as u32 
+        };
+        self.popup_window_state.resize(&self.device, new_size);
+        self.popup_window_state.set_visible(true);
+        let main_window_position = self.main_window_state.window.inner_position().unwrap();
+        let popup_new_position = PhysicalPosition {
+            x: main_window_position.x as u32 + anchor.get_x() as u32 - (new_size.width / 2),
+            y: main_window_position.y as u32 + anchor.get_y() as u32 - new_size.height - 10,
+        };
+        self.popup_window_state.window.set_outer_position(popup_new_position);
+        self.popup_window_state.window.set_window_level(winit::window::WindowLevel::AlwaysOnTop);
+        self.popup_window_state.window.request_redraw();
+    }
+
+    fn hide_popup(&mut self) {
+        self.popup_text = None;
+        self.popup_window_state.set_visible(false);
+        self.popup_window_state.window.request_redraw();
+    }
+
+    /// Replace the symbol whose alternatives are showing with the given one, counting from 1.
+    fn pick_choice(&mut self, number: usize) {
+        let correction = match self.pending_correction {
+            Some(correction) => correction,
+            None => return,
+        };
+        if let Some(lines) = &self.ocr_text {
+            let line = &lines[correction.line_index];
+            let choice = line.get_words()[correction.word_index].get_symbols()[correction.symbol_index]
+                .get_choices()
+                .get(number - 1)
+                .map(|choice| choice.get_text().clone());
+            if let Some(text) = choice {
+                let corrected_line = line.with_correction(correction.word_index, correction.symbol_index, text, self.language, &mut self.glyph_brush);
+                self.record_correction(correction.line_index, &corrected_line.get_text());
+                self.replace_line(correction.line_index, corrected_line);
+                self.pending_correction = None;
+                self.hide_popup();
             }
         }
-        return lines;
     }
-    
+
+    fn replace_line(&mut self, line_index: usize, line: PresentableLine) {
+        if let Some(lines) = &mut self.ocr_text {
+            lines[line_index] = line;
+            self.draw_main_window();
+        }
+    }
+
+    /// Save a crop of the line from the capture it was recognized in, along with the text the user corrected it to.
+    fn record_correction(&self, line_index: usize, corrected_text: &str) {
+        let (correction_log, capture, lines) = match (&self.correction_log, &self.last_capture, &self.ocr_text) {
+            (Some(correction_log), Some(capture), Some(lines)) => (correction_log, capture, lines),
+            _ => return,
+        };
+        let line = &lines[line_index];
+        let (min, max) = line.get_source_bounds();
+        let x = (min.get_x() - CORRECTION_CROP_PADDING).max(0.0) as u32;
+        let y = (min.get_y() - CORRECTION_CROP_PADDING).max(0.0) as u32;
+        let right = ((max.get_x() + CORRECTION_CROP_PADDING).max(0.0) as u32).min(capture.width());
+        let bottom = ((max.get_y() + CORRECTION_CROP_PADDING).max(0.0) as u32).min(capture.height());
+        if right <= x || bottom <= y {
+            return;
+        }
+        let crop = capture.crop_imm(x, y, right - x, bottom - y);
+        if let Err(e) = correction_log.record(&crop, &line.get_text(), corrected_text) {
+            log::error!("Failed to record correction in {}: {}", correction_log.get_directory().display(), e);
+        }
+    }
+
+    /// Start editing the line under the cursor, returning whether there was one.
+    fn start_editing(&mut self) -> bool {
+        let line_index = match &self.ocr_text {
+            Some(lines) => lines.iter().position(|line| line.get_words().iter().any(|word| word.is_highlighted())),
+            None => None,
+        };
+        let line_index = match line_index {
+            Some(line_index) => line_index,
+            None => return false,
+        };
+        let text = self.get_editable_text(&self.ocr_text.as_ref().unwrap()[line_index]);
+        self.pending_correction = None;
+        self.editor = Some(LineEditor {
+            line_index,
+            text,
+            preedit: String::new(),
+            is_ime_active: false,
+        });
+        let (min, max) = self.ocr_text.as_ref().unwrap()[line_index].get_source_bounds();
+        self.main_window_state.window.set_ime_allowed(true);
+        self.main_window_state.window.set_ime_position(PhysicalPosition::new(min.get_x(), max.get_y()));
+        self.show_editor();
+        return true;
+    }
+
+    /// The line's text as shown in the editor, keeping the spaces between words in languages which use them.
+    fn get_editable_text(&self, line: &PresentableLine) -> String {
+        match self.language.get_script().has_word_spaces() {
+            true => line.get_spaced_text(),
+            false => line.get_text(),
+        }
+    }
+
+    fn show_editor(&mut self) {
+        let editor = match &self.editor {
+            Some(editor) => editor,
+            None => return,
+        };
+        let section = Section::default()
+            .to_owned()
+            .with_text(vec![
+                OwnedText::new(format!("{}{}|\n", editor.text, editor.preedit)).with_scale(32.0),
+                OwnedText::new("Enter to save, Esc to cancel").with_scale(16.0),
+            ]);
+        let line = &self.ocr_text.as_ref().unwrap()[editor.line_index];
+        let anchor = PixelPoint::new((line.get_min().get_x() + line.get_max().get_x()) / 2.0, line.get_min().get_y());
+        if let Some(bounds) = self.glyph_brush.glyph_bounds(&section) {
+            self.show_popup(section, bounds, anchor);
+        }
+    }
+
+    fn stop_editing(&mut self, is_saved: bool) {
+        let editor = match self.editor.take() {
+            Some(editor) => editor,
+            None => return,
+        };
+        self.main_window_state.window.set_ime_allowed(false);
+        self.hide_popup();
+        let text = editor.text.trim();
+        if !is_saved {
+            return;
+        }
+        if let Some(lines) = &self.ocr_text {
+            if self.get_editable_text(&lines[editor.line_index]) == text {
+                return;
+            }
+            let corrected_line = match lines[editor.line_index].with_text(text, self.language, &mut self.glyph_brush) {
+                Some(corrected_line) => corrected_line,
+                None => return, //Nothing was typed
+            };
+            self.record_correction(editor.line_index, text);
+            self.replace_line(editor.line_index, corrected_line);
+        }
+    }
+
+    /// Handle keyboard and IME input while a line is being edited, returning whether the event was used.
+    fn handle_editor_input(&mut self, event: &WindowEvent) -> bool {
+        let editor = match &mut self.editor {
+            Some(editor) => editor,
+            None => return false,
+        };
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => match keycode {
+                VirtualKeyCode::Escape => {
+                    self.stop_editing(false);
+                    return true;
+                },
+                VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+                    self.stop_editing(true);
+                    return true;
+                },
+                VirtualKeyCode::Back if editor.preedit.is_empty() => {
+                    editor.text.pop();
+                },
+                VirtualKeyCode::V if self.modifiers.ctrl() => {
+                    match ClipboardContext::new().and_then(|mut clipboard| clipboard.get_contents()) {
+                        Ok(contents) => editor.text.push_str(contents.trim()),
+                        Err(e) => log::error!("Failed to paste from the clipboard: {}", e),
+                    }
+                },
+                _ => {},
+            },
+            WindowEvent::ReceivedCharacter(char) if !editor.is_ime_active && !char.is_control() => editor.text.push(*char),
+            WindowEvent::Ime(Ime::Enabled) => editor.is_ime_active = true,
+            WindowEvent::Ime(Ime::Disabled) => editor.is_ime_active = false,
+            WindowEvent::Ime(Ime::Preedit(text, _)) => editor.preedit = text.clone(),
+            WindowEvent::Ime(Ime::Commit(text)) => {
+                editor.text.push_str(text);
+                editor.preedit.clear();
+            },
+            WindowEvent::MouseInput { .. } => return true, //Keep the editor open rather than showing a translation
+            _ => return false,
+        }
+        self.show_editor();
+        return true;
+    }
+
+}
+
+fn create_geometry_buffer(device: &wgpu::Device, label: &str, usage: BufferUsages, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage: usage | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// The settings which change when switching profile.
+struct OverlayConfig {
+    language: SupportedLanguages,
+    engine: SupportedEngines,
+    layout: OverlayLayout,
+    capture_source: Box<dyn CaptureSource + Send>,
+    worker_settings: OcrWorkerSettings,
+    correction_log: Option<CorrectionLog>,
+}
+
+fn read_config(config: &Config, profile: Option<&str>) -> OverlayConfig {
+    let config = profiles::apply(config, profile);
+    let debug_dump_dir = match config.debug.dump_stages {
+        true => Some(config.debug.dump_dir.clone()),
+        false => None,
+    };
+    let capture_source = capture::from_config(&config.capture).unwrap_or_else(|e| {
+        log::error!("Invalid capture settings, capturing the screen instead: {}", e);
+        Box::new(ScreenSource)
+    });
+    let watch = match config.watch.enabled {
+        true => Some(WatchSettings::new(Duration::from_millis(config.watch.interval_ms), config.watch.threshold)),
+        false => None,
+    };
+    let stability = match config.stability.enabled {
+        true => Some(StabilitySettings::new(
+            config.stability.stable_frames,
+            Duration::from_millis(config.stability.interval_ms),
+            config.stability.threshold,
+            Duration::from_millis(config.stability.max_wait_ms),
+        )),
+        false => None,
+    };
+    dictionary::watch_user_dictionary(config.dictionary.user_dictionary.clone(), config.other.language.get_script());
+    load_language_dictionary(Script::Japanese, config.dictionary.japanese.as_deref());
+    load_language_dictionary(Script::Korean, config.dictionary.korean.as_deref());
+    load_language_dictionary(Script::Cantonese, config.dictionary.cantonese.as_deref());
+    let correction_log = match config.corrections.record {
+        true => Some(CorrectionLog::new(config.corrections.dir.clone())),
+        false => None,
+    };
+    let worker_settings = OcrWorkerSettings::new(config.get_pipeline())
+        .with_debug_dump_dir(debug_dump_dir)
+        .with_watch(watch)
+        .with_stability(stability)
+        .with_cache_capacity(config.cache.capacity);
+    return OverlayConfig {
+        language: config.other.language,
+        engine: config.other.engine,
+        layout: config.other.layout,
+        capture_source,
+        worker_settings,
+        correction_log,
+    };
+}
+
+/// Load the dictionary file for a language without one built in, leaving it without one if none is configured.
+fn load_language_dictionary(script: Script, path: Option<&Path>) {
+    let dictionary = path.and_then(|path| DictionaryFile::load(path, script)
+        .map_err(|e| log::error!("Failed to load the {:?} dictionary from {}: {}", script, path.display(), e))
+        .ok());
+    dictionary::set_language_dictionary(script, dictionary);
+}
+
+/// Start the OCR worker on its own thread, returning it along with the channels used to talk to it.
+/// The worker stops once its channels are dropped.
+fn spawn_ocr_worker(engine: SupportedEngines, language: SupportedLanguages, capture_source: Box<dyn CaptureSource + Send>, worker_settings: OcrWorkerSettings) -> (ChildTask<()>, watch::Sender<CaptureArea>, mpsc::Receiver<WorkerMessage>) {
+    let (main_thread_send_channel, worker_thread_receive_channel) = watch::channel(CaptureArea::default());
+    let (worker_thread_send_channel, main_thread_receive_channel) = mpsc::channel(1);
+    let ocr_thread = ChildTask::from(tokio::task::spawn_blocking(move || {
+        match engine {
+            SupportedEngines::Tesseract => ocr::build_ocr_worker(worker_thread_receive_channel, worker_thread_send_channel, TesseractEngine::new(language), capture_source, worker_settings),
+        }
+    }));
+    return (ocr_thread, main_thread_send_channel, main_thread_receive_channel);
 }
 
 fn configure_main_window(window: Window, surface_format: wgpu::TextureFormat, surface_caps: &wgpu::SurfaceCapabilities, surface: wgpu::Surface, device: &wgpu::Device) -> WindowState {
@@ -506,48 +872,37 @@ fn configure_popup_window(window: Window, surface_format: wgpu::TextureFormat, s
     }
 }
 
-fn get_text_child(nodes: &Vec<Node>) -> String {
-    for node in nodes {
-        if let html_parser::Node::Text(text) = node {
-            return text.to_string();
-        } else if let html_parser::Node::Element(element) = node {
-            return get_text_child(&element.children);
-        }
-    }
-    return "".to_string();
-}
-
-fn parse_bbox_f32(string: &str) -> f32 {
-    let parsed = string.chars().filter(|char| char.is_digit(10)).collect::<String>().parse::<f32>().unwrap();
-    return parsed / 4.0; //OCR image was upscaled 4x before processing
-}
-
-pub async fn screen_entry() {
-    env_logger::init();
-    let mut config_parser = Ini::new();
-    config_parser.load("config.ini").unwrap_or_default();
+/// Open the overlay with the config at the given path, or the default path, using the named profile or otherwise the
+/// one which was active when it was last closed.
+pub async fn screen_entry(config_path: Option<PathBuf>, profile: Option<String>) -> Result<(), ConfigError> {
+    let config_path = config_path.unwrap_or_else(Config::default_path);
+    let config = Config::load(&config_path)?;
+    let profile = match profile {
+        Some(profile) => Some(profiles::find(&config, &profile)?),
+        None => config.profiles.active.as_ref().and_then(|profile| profiles::find(&config, profile)
+            .map_err(|e| log::warn!("Ignoring the active profile: {}", e))
+            .ok()),
+    };
     let event_loop = EventLoop::new();
-    let window_width = config_parser.getfloat("screen", "width").unwrap().or(Some(100.0)).unwrap();
-    let window_height = config_parser.getfloat("screen", "height").unwrap().or(Some(50.0)).unwrap();
-    let window_x = config_parser.getfloat("screen", "x_pos").unwrap().or(Some(100.0)).unwrap();
-    let window_y = config_parser.getfloat("screen", "y_pos").unwrap().or(Some(100.0)).unwrap();
+    //A profile which hasn't been used yet starts wherever the overlay was last
+    let screen = config.get_screen(profile.as_deref());
     let main_window = WindowBuilder::new()
         .with_transparent(true)
-        .with_inner_size(PhysicalSize::new(window_width, window_height))
-        .with_position(PhysicalPosition::new(window_x, window_y))
+        .with_inner_size(PhysicalSize::new(screen.width, screen.height))
+        .with_position(PhysicalPosition::new(screen.x_pos, screen.y_pos))
         .build(&event_loop).unwrap();
     let main_window_id = main_window.id();
     let popup_window = WindowBuilder::new().with_decorations(false).build(&event_loop).unwrap();
     let popup_window_id = popup_window.id();
 
-    let mut window_state = State::new(main_window, popup_window, config_parser).await;
+    let mut window_state = State::new(main_window, popup_window, config, config_path, profile).await;
 
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == window_state.main_window_state.window.id() => if !window_state.input(event) {
+            } if window_id == window_state.main_window_state.window.id() && !window_state.input(event) => {
                 match event {
                     WindowEvent::CloseRequested
                     | WindowEvent::KeyboardInput {
@@ -559,9 +914,22 @@ pub async fn screen_entry() {
                             },
                         ..
                     } => {
-                        window_state.config_parser.write("config.ini").unwrap();
+                        if let Err(e) = window_state.config.save(&window_state.config_path) {
+                            log::error!("{}", e);
+                        }
                         *control_flow = ControlFlow::Exit}
                         ,
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(keycode),
+                                ..
+                            },
+                        ..
+                    } if choice_number(*keycode).is_some() => {
+                        window_state.pick_choice(choice_number(*keycode).unwrap());
+                    }
                     WindowEvent::Resized(physical_size) => {
                         window_state.main_window_state.resize(&window_state.device, *physical_size);
                     }
@@ -571,13 +939,11 @@ pub async fn screen_entry() {
                     WindowEvent::Moved(_) => {
                         window_state.main_window_state.window.request_redraw();
                     }
-                    WindowEvent::CursorMoved { device_id: _, position, modifiers: _ } => {
+                    WindowEvent::CursorMoved { position, .. } => {
                         window_state.handle_cursor(&PixelPoint::from(position));
                     }
-                    WindowEvent::MouseInput { device_id: _, state, button: _, modifiers: _ } => {
-                        if let ElementState::Released = state {
-                            window_state.handle_click();
-                        }
+                    WindowEvent::MouseInput { state: ElementState::Released, .. } => {
+                        window_state.handle_click();
                     }
                     _ => {}
                 }
@@ -617,13 +983,15 @@ pub async fn screen_entry() {
                     if trigger_time <= Instant::now() {
                         window_state.ocr_job_timer = None;
                         let window_size = window_state.main_window_state.window.inner_size();
-                        window_state.config_parser.set("screen", "width", Some((window_size.width as f64).to_string()));
-                        window_state.config_parser.set("screen", "height", Some((window_size.height as f64).to_string()));
                         let window_inner_position = window_state.main_window_state.window.inner_position().unwrap();
                         let window_outer_position = window_state.main_window_state.window.outer_position().unwrap();
-                        window_state.config_parser.set("screen", "x_pos", Some((window_outer_position.x as f64).to_string()));
-                        window_state.config_parser.set("screen", "y_pos", Some((window_outer_position.y as f64).to_string()));
-                        window_state.ocr_send_channel.send((window_inner_position.x, window_inner_position.y, window_size.width, window_size.height)).unwrap();
+                        window_state.config.set_screen(window_state.profile.as_deref(), ScreenConfig {
+                            x_pos: window_outer_position.x,
+                            y_pos: window_outer_position.y,
+                            width: window_size.width,
+                            height: window_size.height,
+                        });
+                        window_state.ocr_send_channel.send(CaptureArea::new(window_inner_position.x, window_inner_position.y, window_size.width, window_size.height)).unwrap();
                     }
                 }
                 window_state.check_running_job();
@@ -633,4 +1001,21 @@ pub async fn screen_entry() {
         }
     });
     
-}
\ No newline at end of file
+}
+
+/// The alternative picked by a number key, counting from 1.
+fn choice_number(keycode: VirtualKeyCode) -> Option<usize> {
+    let number = match keycode {
+        VirtualKeyCode::Key1 | VirtualKeyCode::Numpad1 => 1,
+        VirtualKeyCode::Key2 | VirtualKeyCode::Numpad2 => 2,
+        VirtualKeyCode::Key3 | VirtualKeyCode::Numpad3 => 3,
+        VirtualKeyCode::Key4 | VirtualKeyCode::Numpad4 => 4,
+        VirtualKeyCode::Key5 | VirtualKeyCode::Numpad5 => 5,
+        VirtualKeyCode::Key6 | VirtualKeyCode::Numpad6 => 6,
+        VirtualKeyCode::Key7 | VirtualKeyCode::Numpad7 => 7,
+        VirtualKeyCode::Key8 | VirtualKeyCode::Numpad8 => 8,
+        VirtualKeyCode::Key9 | VirtualKeyCode::Numpad9 => 9,
+        _ => return None,
+    };
+    return Some(number);
+}
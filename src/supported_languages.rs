@@ -1,11 +1,64 @@
 use std::fmt;
 
+use clap::ValueEnum;
 use serde::{Serialize, Deserialize};
 
-#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+/// The languages which can be read, named after the Tesseract traineddata each one uses.
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize, ValueEnum)]
 pub enum SupportedLanguages {
     ChiTra,
     ChiSim,
+    ChiTraVert,
+    ChiSimVert,
+    Jpn,
+    JpnVert,
+    Kor,
+    /// Written Cantonese, which Tesseract has no model for, so it is read with `chi_tra` and only looked up differently.
+    Yue,
+}
+
+/// The writing system of a language, which decides how its text is split into words and looked up.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum Script {
+    Chinese,
+    Japanese,
+    Korean,
+    /// Chinese characters looked up in a Cantonese dictionary before CC-CEDICT, with Jyutping readings.
+    Cantonese,
+}
+
+impl Script {
+    /// Whether words are separated by spaces, in which case the engine's own word boundaries are kept rather than
+    /// tokenizing with a dictionary.
+    pub fn has_word_spaces(&self) -> bool {
+        *self == Self::Korean
+    }
+
+    /// Whether the text is written in Chinese characters, which CC-CEDICT can look up and tokenize.
+    pub fn is_chinese(&self) -> bool {
+        matches!(self, Self::Chinese | Self::Cantonese)
+    }
+}
+
+impl SupportedLanguages {
+    pub fn get_script(&self) -> Script {
+        match self {
+            Self::ChiTra | Self::ChiSim | Self::ChiTraVert | Self::ChiSimVert => Script::Chinese,
+            Self::Jpn | Self::JpnVert => Script::Japanese,
+            Self::Kor => Script::Korean,
+            Self::Yue => Script::Cantonese,
+        }
+    }
+
+    /// Whether the text is written in columns from top to bottom, read from right to left.
+    pub fn is_vertical(&self) -> bool {
+        matches!(self, Self::ChiTraVert | Self::ChiSimVert | Self::JpnVert)
+    }
+
+    /// Whether dictionary entries should be shown in traditional characters.
+    pub fn is_traditional(&self) -> bool {
+        matches!(self, Self::ChiTra | Self::ChiTraVert | Self::Yue)
+    }
 }
 
 impl fmt::Display for SupportedLanguages {
@@ -13,6 +66,27 @@ impl fmt::Display for SupportedLanguages {
         match self {
             Self::ChiTra => write!(f, "chi_tra"),
             Self::ChiSim => write!(f, "chi_sim"),
+            Self::ChiTraVert => write!(f, "chi_tra_vert"),
+            Self::ChiSimVert => write!(f, "chi_sim_vert"),
+            Self::Jpn => write!(f, "jpn"),
+            Self::JpnVert => write!(f, "jpn_vert"),
+            Self::Kor => write!(f, "kor"),
+            Self::Yue => write!(f, "chi_tra"),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cantonese_reads_with_the_traditional_chinese_model() {
+        assert_eq!(SupportedLanguages::Yue.to_string(), "chi_tra");
+        assert!(SupportedLanguages::Yue.is_traditional());
+        assert!(!SupportedLanguages::Yue.is_vertical());
+        assert_eq!(SupportedLanguages::Yue.get_script(), Script::Cantonese);
+        assert!(Script::Cantonese.is_chinese());
+        assert!(!Script::Cantonese.has_word_spaces());
+    }
+}
@@ -0,0 +1,297 @@
+use std::{ffi::{CStr, CString}, os::raw::{c_char, c_int}, ptr};
+
+use image::GrayImage;
+use thiserror::Error;
+use tesseract_sys::{
+    TessBaseAPI, TessBaseAPICreate, TessBaseAPIDelete, TessBaseAPIGetHOCRText, TessBaseAPIGetIterator, TessBaseAPIInit3,
+    TessBaseAPIRecognize, TessBaseAPISetImage, TessBaseAPISetPageSegMode, TessBaseAPISetVariable, TessChoiceIteratorConfidence,
+    TessChoiceIteratorDelete, TessChoiceIteratorGetUTF8Text, TessChoiceIteratorNext, TessDeleteText, TessPageIterator, TessPageIteratorBoundingBox,
+    TessPageIteratorIsAtBeginningOf, TessPageIteratorLevel_RIL_SYMBOL, TessPageIteratorLevel_RIL_TEXTLINE, TessPageIteratorLevel_RIL_WORD,
+    TessPageIteratorOrientation, TessPageSegMode_PSM_SINGLE_BLOCK, TessPageSegMode_PSM_SINGLE_BLOCK_VERT_TEXT,
+    TessResultIterator, TessResultIteratorConfidence, TessResultIteratorDelete, TessResultIteratorGetChoiceIterator,
+    TessResultIteratorGetPageIterator, TessResultIteratorGetUTF8Text, TessResultIteratorNext,
+    TessWritingDirection_WRITING_DIRECTION_TOP_TO_BOTTOM,
+};
+
+#[derive(Debug, Error)]
+pub enum TesseractApiError {
+    #[error("Failed to initialize Tesseract with language {0}")]
+    Initialize(String),
+    #[error("Failed to set Tesseract variable {0}")]
+    SetVariable(&'static str),
+    #[error("Tesseract failed to recognize the image")]
+    Recognize,
+    #[error("Image is too large for Tesseract")]
+    ImageTooLarge,
+}
+
+/// An alternative reading which Tesseract considered for a symbol.
+#[derive(Debug, Clone)]
+pub struct RawChoice {
+    pub text: String,
+    pub confidence: f32,
+}
+
+/// A single symbol from Tesseract's result iterator, with its box in the coordinates of the recognized image.
+#[derive(Debug, Clone)]
+pub struct RawSymbol {
+    pub text: String,
+    pub bbox: [i32; 4],
+    pub confidence: f32,
+    pub choices: Vec<RawChoice>,
+    pub is_line_start: bool,
+    pub is_word_start: bool,
+    /// Whether Tesseract reads the symbol's line from top to bottom.
+    pub is_vertical: bool,
+}
+
+/// Owns a Tesseract instance through the C API, which exposes the result iterator that the `tesseract` crate hides.
+///
+/// The handle is never null, is only freed on drop and never leaves this struct, so every call below can rely on it
+/// pointing to a live `TessBaseAPI` which nothing else is using.
+pub struct TesseractApi {
+    handle: *mut TessBaseAPI,
+}
+
+// SAFETY: A `TessBaseAPI` keeps all of its state in the instance and has no affinity to the thread which created it.
+// Tesseract documents instances as not thread safe, only that separate instances may run on separate threads, so the
+// handle must never be used from two threads at once. Every method takes `&mut self` and the raw pointer keeps this
+// type `!Sync`, so moving the whole instance to another thread is the only thing this allows.
+unsafe impl Send for TesseractApi {}
+
+impl TesseractApi {
+    pub fn new(language: &str) -> Result<Self, TesseractApiError> {
+        let c_language = CString::new(language).map_err(|_| TesseractApiError::Initialize(language.to_string()))?;
+        // SAFETY: Takes no arguments and returns a new instance owned by the caller, or null if allocation failed.
+        let handle = unsafe { TessBaseAPICreate() };
+        if handle.is_null() {
+            return Err(TesseractApiError::Initialize(language.to_string()));
+        }
+        let api = Self {
+            handle,
+        };
+        // SAFETY: The handle is live, a null data path makes Tesseract use TESSDATA_PREFIX, and the language is a
+        // nul terminated string which Tesseract copies before returning. If this fails `api` is dropped, which deletes
+        // the instance.
+        if unsafe { TessBaseAPIInit3(api.handle, ptr::null(), c_language.as_ptr()) } != 0 {
+            return Err(TesseractApiError::Initialize(language.to_string()));
+        }
+        // SAFETY: The handle is live and PSM_SINGLE_BLOCK is one of the page segmentation modes the enum defines.
+        unsafe { TessBaseAPISetPageSegMode(api.handle, TessPageSegMode_PSM_SINGLE_BLOCK) };
+        return Ok(api);
+    }
+
+    /// Read the image as a single block of vertical text, for languages written in columns.
+    pub fn set_vertical_text(&mut self) {
+        // SAFETY: The handle is live and PSM_SINGLE_BLOCK_VERT_TEXT is one of the modes the enum defines.
+        unsafe { TessBaseAPISetPageSegMode(self.handle, TessPageSegMode_PSM_SINGLE_BLOCK_VERT_TEXT) };
+    }
+
+    pub fn set_variable(&mut self, name: &'static str, value: &str) -> Result<(), TesseractApiError> {
+        let c_name = CString::new(name).map_err(|_| TesseractApiError::SetVariable(name))?;
+        let c_value = CString::new(value).map_err(|_| TesseractApiError::SetVariable(name))?;
+        // SAFETY: The handle is live and both strings are nul terminated. Tesseract parses the value into its own
+        // parameter before returning, so neither string is kept.
+        if unsafe { TessBaseAPISetVariable(self.handle, c_name.as_ptr(), c_value.as_ptr()) } == 0 {
+            return Err(TesseractApiError::SetVariable(name));
+        }
+        return Ok(());
+    }
+
+    /// Recognize the image, returning every symbol in reading order along with up to `max_choices` alternatives each.
+    pub fn recognize(&mut self, image: &GrayImage, max_choices: usize) -> Result<Vec<RawSymbol>, TesseractApiError> {
+        let width = c_int::try_from(image.width()).map_err(|_| TesseractApiError::ImageTooLarge)?;
+        let height = c_int::try_from(image.height()).map_err(|_| TesseractApiError::ImageTooLarge)?;
+        // SAFETY: The handle is live. A `GrayImage` is one byte per pixel with no padding between rows, so the buffer
+        // holds exactly `height` rows of `width` bytes, matching the 1 byte per pixel and `width` bytes per line given
+        // here. Tesseract copies the pixels into its own image during SetImage, so the borrow of `image` doesn't need
+        // to outlive this call. A null monitor makes Recognize run without a timeout or progress callback.
+        unsafe {
+            TessBaseAPISetImage(self.handle, image.as_raw().as_ptr(), width, height, 1, width);
+            if TessBaseAPIRecognize(self.handle, ptr::null_mut()) != 0 {
+                return Err(TesseractApiError::Recognize);
+            }
+        }
+        // SAFETY: The handle is live and has just recognized an image. The iterator is a new allocation owned by us,
+        // which stays valid until the next SetImage or Recognize on this instance, and both of those need `&mut self`.
+        let iterator = unsafe { TessBaseAPIGetIterator(self.handle) };
+        if iterator.is_null() {
+            return Ok(Vec::new()); //No text was found
+        }
+        // SAFETY: The iterator is non-null and live, as above, and isn't used after it is deleted on the next line.
+        let symbols = unsafe { read_symbols(iterator, max_choices) };
+        // SAFETY: We own the iterator, `read_symbols` doesn't keep it, and nothing else deletes it.
+        unsafe { TessResultIteratorDelete(iterator) };
+        return Ok(symbols);
+    }
+
+    /// The hOCR for the most recently recognized image.
+    pub fn get_hocr_text(&mut self) -> Option<String> {
+        // SAFETY: The handle is live. The text is a new allocation owned by us, or null if nothing has been recognized,
+        // and `take_text` frees it with TessDeleteText.
+        return unsafe { take_text(TessBaseAPIGetHOCRText(self.handle, 0)) };
+    }
+}
+
+impl Drop for TesseractApi {
+    fn drop(&mut self) {
+        // SAFETY: The handle came from TessBaseAPICreate and isn't freed anywhere else. Every iterator from it has
+        // already been deleted, since `recognize` deletes its iterator before returning.
+        unsafe { TessBaseAPIDelete(self.handle) };
+    }
+}
+
+/// Read every symbol from a result iterator, leaving it at the end of the page.
+///
+/// # Safety
+/// The iterator must be non-null and live, and its `TessBaseAPI` must not recognize another image until this returns.
+unsafe fn read_symbols(iterator: *mut TessResultIterator, max_choices: usize) -> Vec<RawSymbol> {
+    // SAFETY: The caller guarantees the iterator is live. The page iterator is the same object seen through its base
+    // class, so it is neither owned nor freed here, and it is only used while the result iterator is.
+    let page_iterator = TessResultIteratorGetPageIterator(iterator);
+    let mut symbols = Vec::new();
+    loop {
+        // SAFETY: The text is a new allocation owned by us, or null for an empty symbol, and `take_text` frees it with
+        // TessDeleteText. The other calls only read the iterator's current position, writing into local variables.
+        if let Some(text) = take_text(TessResultIteratorGetUTF8Text(iterator, TessPageIteratorLevel_RIL_SYMBOL)) {
+            let mut bbox = [0; 4];
+            TessPageIteratorBoundingBox(page_iterator, TessPageIteratorLevel_RIL_SYMBOL, &mut bbox[0], &mut bbox[1], &mut bbox[2], &mut bbox[3]);
+            symbols.push(RawSymbol {
+                text,
+                bbox,
+                confidence: TessResultIteratorConfidence(iterator, TessPageIteratorLevel_RIL_SYMBOL),
+                choices: read_choices(iterator, max_choices),
+                is_line_start: TessPageIteratorIsAtBeginningOf(page_iterator, TessPageIteratorLevel_RIL_TEXTLINE) != 0,
+                is_word_start: TessPageIteratorIsAtBeginningOf(page_iterator, TessPageIteratorLevel_RIL_WORD) != 0,
+                is_vertical: is_vertical(page_iterator),
+            });
+        }
+        // SAFETY: The iterator is live. Returns 0 once it has moved past the last symbol, after which it isn't read.
+        if TessResultIteratorNext(iterator, TessPageIteratorLevel_RIL_SYMBOL) == 0 {
+            break;
+        }
+    }
+    return symbols;
+}
+
+/// # Safety
+/// The page iterator must be non-null and live.
+unsafe fn is_vertical(page_iterator: *mut TessPageIterator) -> bool {
+    let mut orientation = 0;
+    let mut writing_direction = 0;
+    let mut textline_order = 0;
+    let mut deskew_angle = 0.0;
+    // SAFETY: The caller guarantees the iterator is live, and every out parameter points to a local variable.
+    TessPageIteratorOrientation(page_iterator, &mut orientation, &mut writing_direction, &mut textline_order, &mut deskew_angle);
+    return writing_direction == TessWritingDirection_WRITING_DIRECTION_TOP_TO_BOTTOM;
+}
+
+/// Read up to `max_choices` alternatives for the symbol the result iterator is on.
+///
+/// # Safety
+/// The iterator must be non-null and live.
+unsafe fn read_choices(iterator: *mut TessResultIterator, max_choices: usize) -> Vec<RawChoice> {
+    // SAFETY: The caller guarantees the result iterator is live. The choice iterator is a new allocation owned by us,
+    // or null when the symbol has no alternatives, and it is deleted below before the result iterator moves on.
+    let choice_iterator = TessResultIteratorGetChoiceIterator(iterator);
+    if choice_iterator.is_null() {
+        return Vec::new();
+    }
+    let mut choices = Vec::with_capacity(max_choices);
+    while choices.len() < max_choices {
+        // SAFETY: The choice iterator is live. Unlike the result iterator's text, this string belongs to the choice
+        // iterator and must not be passed to TessDeleteText, so it is copied before the iterator moves or is deleted.
+        let text = TessChoiceIteratorGetUTF8Text(choice_iterator);
+        if !text.is_null() {
+            choices.push(RawChoice {
+                text: CStr::from_ptr(text).to_string_lossy().into_owned(),
+                confidence: TessChoiceIteratorConfidence(choice_iterator),
+            });
+        }
+        if TessChoiceIteratorNext(choice_iterator) == 0 {
+            break;
+        }
+    }
+    // SAFETY: We own the choice iterator and none of its strings are used after this.
+    TessChoiceIteratorDelete(choice_iterator);
+    return choices;
+}
+
+/// Copy and free a string allocated by Tesseract.
+///
+/// # Safety
+/// The text must be null, or a nul terminated string which Tesseract allocated for the caller to free, such as from
+/// TessBaseAPIGetHOCRText or TessResultIteratorGetUTF8Text, and which isn't used again afterwards.
+unsafe fn take_text(text: *mut c_char) -> Option<String> {
+    if text.is_null() {
+        return None;
+    }
+    // SAFETY: The caller guarantees the text is a live, nul terminated string, which is copied before it is freed.
+    let owned = CStr::from_ptr(text).to_string_lossy().into_owned();
+    // SAFETY: Tesseract allocated the text with `new[]`, which TessDeleteText frees with the matching `delete[]`.
+    TessDeleteText(text);
+    return Some(owned);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glyph_brush::ab_glyph::{Font, FontRef, PxScale, ScaleFont, point};
+    use image::Luma;
+
+    /// Draw black text on a white background with the bundled Inconsolata, which only has Latin glyphs.
+    fn render(text: &str) -> GrayImage {
+        let font = FontRef::try_from_slice(include_bytes!("Inconsolata-Regular.ttf")).unwrap();
+        let font = font.as_scaled(PxScale::from(64.0));
+        let mut image = GrayImage::from_pixel(64 + text.chars().count() as u32 * 40, 128, Luma([255]));
+        let mut x = 32.0;
+        for char in text.chars() {
+            let mut glyph = font.scaled_glyph(char);
+            glyph.position = point(x, 32.0 + font.ascent());
+            let advance = font.h_advance(glyph.id);
+            if let Some(outline) = font.outline_glyph(glyph) {
+                let bounds = outline.px_bounds();
+                outline.draw(|glyph_x, glyph_y, coverage| {
+                    let pixel = image.get_pixel_mut(bounds.min.x as u32 + glyph_x, bounds.min.y as u32 + glyph_y);
+                    pixel.0[0] = pixel.0[0].min((255.0 * (1.0 - coverage)) as u8);
+                });
+            }
+            x += advance;
+        }
+        return image;
+    }
+
+    #[test]
+    #[ignore = "needs libtesseract and eng.traineddata, run with cargo test -- --ignored"]
+    fn recognizes_symbols_with_the_installed_library() {
+        let image = render("HELLO 42");
+        let mut tesseract = TesseractApi::new("eng").unwrap();
+        let symbols = tesseract.recognize(&image, 3).unwrap();
+        let text: String = symbols.iter().map(|symbol| symbol.text.as_str()).collect();
+        assert_eq!(text, "HELLO42");
+        assert!(symbols[0].is_line_start && symbols[0].is_word_start);
+        assert!(!symbols[1].is_line_start && !symbols[1].is_word_start);
+        assert!(symbols[5].is_word_start);
+        for pair in symbols.windows(2) {
+            assert!(pair[0].bbox[0] < pair[1].bbox[0]);
+        }
+        for symbol in &symbols {
+            let [left, top, right, bottom] = symbol.bbox;
+            assert!(0 <= left && left < right && right <= image.width() as i32);
+            assert!(0 <= top && top < bottom && bottom <= image.height() as i32);
+            assert!((0.0 ..= 100.0).contains(&symbol.confidence));
+            assert!(!symbol.choices.is_empty() && symbol.choices.len() <= 3);
+            assert!(!symbol.is_vertical);
+        }
+        assert!(tesseract.get_hocr_text().unwrap().contains("ocrx_word"));
+        //The instance is reused for the next image, so the first image's iterator must not leak into it
+        assert!(tesseract.recognize(&GrayImage::from_pixel(64, 64, Luma([255])), 3).unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs libtesseract, run with cargo test -- --ignored"]
+    fn fails_to_initialize_missing_languages() {
+        assert!(matches!(TesseractApi::new("not_a_language"), Err(TesseractApiError::Initialize(_))));
+        assert!(matches!(TesseractApi::new("eng\0"), Err(TesseractApiError::Initialize(_))));
+    }
+}